@@ -0,0 +1,382 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+use libc;
+use ::datafile::{self, Header, MAGIC};
+use ::mancala::{GameState, PlayerTurn, RuleSet, MAX_HOUSES};
+use ::value::{Model, Table, ValueFunction};
+
+/// Bytes in a value: an f32, little endian
+const VALUE_BYTES: usize = 4;
+
+/// Bytes in a count of visits: a u32, little endian. Records didn't have one before version 2.
+const VISIT_BYTES: usize = 4;
+
+/// Bytes in a packed state on boards of `pits` houses a side: one per house and store, and one
+/// for the player to move
+fn key_bytes(pits: usize) -> usize {
+    2 * pits + 3
+}
+
+/// Pack `state` into `key`. Rules and board size aren't included, the header covers them.
+fn pack(state: &GameState, key: &mut [u8]) {
+    let houses = state.houses();
+    key[..houses.len()].copy_from_slice(houses);
+    key[houses.len()] = if state.to_move() == PlayerTurn::P1 { 0 } else { 1 };
+}
+
+fn put_u32(bytes: &mut [u8], n: u32) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (n >> (8 * i)) as u8;
+    }
+}
+
+fn get_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |n, &byte| n << 8 | byte as u32)
+}
+
+fn unpack(key: &[u8], rules: RuleSet) -> Result<GameState, String> {
+    let pits = (key.len() - 3) / 2;
+    let to_move = match key[2 * pits + 2] {
+        0 => PlayerTurn::P1,
+        1 => PlayerTurn::P2,
+        other => return Err(format!("no player {} to move", other + 1)),
+    };
+    GameState::from_sides(&key[..pits], key[pits], &key[pits+1..2*pits+1], key[2*pits+1], rules,
+                          to_move)
+}
+
+/// A file mapped read-only into memory
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn open(path: &str) -> Result<Mmap, String> {
+        let file = try!(File::open(path).map_err(|e| format!("can't open {}: {}", path, e)));
+        let len = try!(file.metadata().map_err(|e| format!("can't read {}: {}", path, e)))
+            .len() as usize;
+        if len < MAGIC.len() {
+            return Err(format!("{} is too short to be a training datafile", path));
+        }
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(),
+                       0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(format!("can't map {}: {}", path, io::Error::last_os_error()));
+        }
+        Ok(Mmap { ptr: ptr, len: len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Pack every state in `values` into one buffer of records, with its value and visits,
+/// returning it with the record indices in order of state
+fn pack_records(values: &HashMap<GameState, f64>, visits: &HashMap<GameState, u32>,
+                pits: usize) -> (Vec<u8>, Vec<usize>) {
+    let key_len = key_bytes(pits);
+    let width = key_len + VALUE_BYTES + VISIT_BYTES;
+    let mut records = vec![0; values.len() * width];
+    for (record, (state, &value)) in records.chunks_mut(width).zip(values) {
+        pack(state, &mut record[..key_len]);
+        let (value_bytes, visit_bytes) = record[key_len..].split_at_mut(VALUE_BYTES);
+        put_u32(value_bytes, (value as f32).to_bits());
+        put_u32(visit_bytes, visits.get(state).cloned().unwrap_or(0));
+    }
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| records[a * width..a * width + key_len]
+                  .cmp(&records[b * width..b * width + key_len]));
+    (records, order)
+}
+
+/// Write `header` then the records of `base`, already sorted, merged with those of `learned` in
+/// the given `order`, to the training datafile at `path`. Records of `learned` replace those of
+/// `base` for the same state.
+fn write_records(header: &Header, path: &str, base: &[u8], learned: &[u8],
+                 order: &[usize]) -> Result<(), String> {
+    let key_len = key_bytes(header.pits);
+    let width = key_len + VALUE_BYTES + VISIT_BYTES;
+    let encoded_header = try!(datafile::encode_header(header));
+    datafile::write_atomically_with(path, |out| {
+        try!(out.write_all(&encoded_header));
+        let mut base = base.chunks(width).peekable();
+        let mut learned = order.iter().map(|&i| &learned[i * width..(i + 1) * width]).peekable();
+        loop {
+            let ordering = match (base.peek(), learned.peek()) {
+                (Some(b), Some(l)) => b[..key_len].cmp(&l[..key_len]),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return Ok(()),
+            };
+            let record = match ordering {
+                Ordering::Less => base.next(),
+                Ordering::Greater => learned.next(),
+                Ordering::Equal => {
+                    base.next();
+                    learned.next()
+                }
+            };
+            try!(out.write_all(record.unwrap()));
+        }
+    })
+}
+
+/// A table of values saved as one record per state, each the packed state followed by its
+/// value as an f32 and its visits, sorted by state. It's memory-mapped rather than read, so even
+/// huge tables open instantly and only the pages lookups touch are ever loaded, and states are
+/// found by binary search. Updates are kept in memory apart from the mapped records and merged
+/// in when saved, so training on it only costs memory for the states it reaches.
+pub struct CompactTable {
+    /// Where it's mapped from, to name in errors
+    path: String,
+    map: Mmap,
+    /// Where the records start, after the header
+    offset: usize,
+    rules: RuleSet,
+    pits: usize,
+    /// `VISIT_BYTES`, or none for records from before visits were counted
+    visit_bytes: usize,
+    /// What's been learned since opening, for states whose mapped records it replaces
+    learned: Table,
+    /// How many of the learned states have no mapped record
+    new_states: usize,
+}
+
+impl CompactTable {
+    /// Map the compact table in the training datafile at `path`
+    pub fn open(path: &str) -> Result<(Header, CompactTable), String> {
+        let map = try!(Mmap::open(path));
+        let (header, offset) = {
+            let bytes = map.bytes();
+            let (header, records) = try!(datafile::decode_header(path, bytes));
+            if header.model != Model::Compact {
+                return Err(format!("{} holds {:?} values, not Compact", path, header.model));
+            }
+            (header, bytes.len() - records.len())
+        };
+        let visit_bytes = if header.version < 2 { 0 } else { VISIT_BYTES };
+        let table = CompactTable { path: path.to_string(), map: map, offset: offset,
+                                   rules: header.rules, pits: header.pits, visit_bytes: visit_bytes,
+                                   learned: Table::with_capacity(0), new_states: 0 };
+        if table.records().len() % table.width() != 0 {
+            return Err(format!("{} is truncated", path));
+        }
+        Ok((header, table))
+    }
+
+    /// Write a table's `values` and `visits` compactly to the training datafile at `path`, after
+    /// `header`
+    pub fn save(values: &HashMap<GameState, f64>, visits: &HashMap<GameState, u32>,
+                header: &Header, path: &str) -> Result<(), String> {
+        let (records, order) = pack_records(values, visits, header.pits);
+        write_records(header, path, &[], &records, &order)
+    }
+
+    fn records(&self) -> &[u8] {
+        &self.map.bytes()[self.offset..]
+    }
+
+    /// Bytes in each record
+    fn width(&self) -> usize {
+        key_bytes(self.pits) + VALUE_BYTES + self.visit_bytes
+    }
+
+    fn record_count(&self) -> usize {
+        self.records().len() / self.width()
+    }
+
+    /// The packed state, value and visits of the record at `index`
+    fn record(&self, index: usize) -> (&[u8], f64, u32) {
+        let (width, key_len) = (self.width(), key_bytes(self.pits));
+        let record = &self.records()[index * width..(index + 1) * width];
+        let value = f32::from_bits(get_u32(&record[key_len..key_len + VALUE_BYTES]));
+        (&record[..key_len], value as f64, get_u32(&record[key_len + VALUE_BYTES..]))
+    }
+
+    /// The value and visits in the mapped records for `state`, ignoring anything learned since
+    fn mapped(&self, state: &GameState) -> Option<(f64, u32)> {
+        if state.pits() != self.pits {
+            return None;
+        }
+        let mut key = [0; MAX_HOUSES + 1];
+        let key = &mut key[..key_bytes(self.pits)];
+        pack(state, key);
+        let (mut low, mut high) = (0, self.record_count());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (middle_key, value, visits) = self.record(middle);
+            match middle_key.cmp(key) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Some((value, visits)),
+            }
+        }
+        None
+    }
+
+    /// Bring the mapped record for `state` into what's been learned, before changing it the
+    /// first time. Returns whether the state has a value.
+    fn learn(&mut self, state: &GameState) -> bool {
+        if self.learned.values.contains_key(state) {
+            return true;
+        }
+        match self.mapped(state) {
+            Some((value, visits)) => {
+                self.learned.values.insert(*state, value);
+                self.learned.visits.insert(*state, visits);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every state with its value and visits, to save in another layout
+    pub fn to_table(&self) -> Result<Table, String> {
+        let mut table = Table::with_capacity(self.record_count());
+        for index in 0..self.record_count() {
+            let (key, value, visits) = self.record(index);
+            let state = try!(unpack(key, self.rules).map_err(|e| {
+                format!("{} has a bad state in record {}: {}", self.path, index, e)
+            }));
+            table.values.insert(state, value);
+            if visits > 0 {
+                table.visits.insert(state, visits);
+            }
+        }
+        table.values.extend(&self.learned.values);
+        table.visits.extend(&self.learned.visits);
+        Ok(table)
+    }
+}
+
+impl ValueFunction for CompactTable {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        ValueFunction::get(&self.learned, state).or_else(|| self.mapped(state).map(|m| m.0))
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        if !self.learn(state) {
+            self.new_states += 1;
+        }
+        self.learned.update(state, target, learning_rate);
+    }
+
+    fn visit(&mut self, state: &GameState) {
+        self.learn(state);
+        self.learned.visit(state);
+    }
+
+    fn len(&self) -> usize {
+        self.record_count() + self.new_states
+    }
+
+    /// Saved by merging what's been learned into the mapped records, if the header asks for a
+    /// compact table and they're laid out the same
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        if header.model != Model::Compact || header.pits != self.pits ||
+            self.visit_bytes != VISIT_BYTES {
+            return try!(self.to_table()).save(path, header);
+        }
+        let (learned, order) = pack_records(&self.learned.values, &self.learned.visits, self.pits);
+        write_records(header, path, self.records(), &learned, &order)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::OpenOptions;
+    use ::datafile::{self, Header};
+    use ::learning::TrainingHistory;
+    use ::mancala::{GameState, RuleSet};
+    use ::value::{Model, Table, ValueFunction};
+
+    #[test]
+    fn test_compact_table() {
+        let start = GameState::with_board(4, 3, RuleSet::kalah());
+        let mut table = Table::with_capacity(4);
+        for action in start.gen_actions() {
+            let state = start.afterstate(action);
+            table.values.insert(state, state.houses()[4] as f64 / 8.0);
+            table.visits.insert(state, state.houses()[5] as u32);
+        }
+        let path = env::temp_dir().join("mancala-test-compact.dat");
+        let path = path.to_str().unwrap();
+        let header = Header::new(Model::Compact, &start, TrainingHistory::new());
+        CompactTable::save(&table.values, &table.visits, &header, path).unwrap();
+
+        let (loaded_header, compact) = CompactTable::open(path).unwrap();
+        assert_eq!(loaded_header, header);
+        assert_eq!(compact.len(), table.len());
+        for (state, &value) in &table.values {
+            assert_eq!(compact.get(state), Some(value));
+        }
+        assert_eq!(compact.get(&start), None);
+        assert_eq!(compact.get(&GameState::new(3)), None);
+        table.visits.retain(|_, visits| *visits > 0);
+        assert_eq!(compact.to_table().unwrap(), table);
+        for index in 1..compact.len() {
+            assert!(compact.record(index - 1).0 < compact.record(index).0);
+        }
+
+        // Learning goes into memory, and saving merges it into the records
+        let (_, mut compact) = CompactTable::open(path).unwrap();
+        let known = *table.values.keys().next().unwrap();
+        compact.update(&known, 1.0, 0.5);
+        compact.visit(&known);
+        compact.update(&start, 1.0, 0.5);
+        compact.visit(&start);
+        assert_eq!(compact.get(&start), Some(0.75));
+        assert_eq!(compact.len(), table.len() + 1);
+        let learned = compact.to_table().unwrap();
+        assert_eq!(learned.values[&known], (table.values[&known] + 1.0) / 2.0);
+        assert_eq!(learned.visits(&known), table.visits(&known) + 1);
+        assert_eq!(learned.visits(&start), 1);
+        compact.save(path, &header).unwrap();
+        let (_, merged) = CompactTable::open(path).unwrap();
+        assert_eq!(merged.to_table().unwrap(), learned);
+        for index in 1..merged.len() {
+            assert!(merged.record(index - 1).0 < merged.record(index).0);
+        }
+
+        // Saving a table under a compact header writes it compactly too
+        table.save(path, &header).unwrap();
+        assert_eq!(CompactTable::open(path).unwrap().1.len(), table.len());
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        let length = file.metadata().unwrap().len();
+        file.set_len(length - 1).unwrap();
+        assert!(CompactTable::open(path).err().unwrap().contains("truncated"));
+
+        // A corrupt record is an error naming it rather than a panic
+        let mut encoded = datafile::encode_header(&header).unwrap();
+        let record_start = encoded.len();
+        let (records, order) = pack_records(&table.values, &table.visits, 4);
+        let width = key_bytes(4) + VALUE_BYTES + VISIT_BYTES;
+        for &i in &order {
+            encoded.extend(&records[i * width..(i + 1) * width]);
+        }
+        encoded[record_start + width + key_bytes(4) - 1] = 7;
+        datafile::write_atomically(&encoded, path).unwrap();
+        let (_, corrupt) = CompactTable::open(path).unwrap();
+        assert!(corrupt.to_table().err().unwrap()
+                .ends_with("has a bad state in record 1: no player 8 to move"));
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode, decode_from};
+use rustc_serialize::{Decodable, Encodable};
+use ::learning::TrainingHistory;
+use ::mancala::{GameState, RuleSet};
+use ::value::{Model, ValueFunction};
+
+/// The first bytes of every training datafile
+pub const MAGIC: &'static [u8; 8] = b"MANCALA\0";
+
+/// The version of the training datafile layout written now. Bump it whenever the header or the
+/// way any model is encoded changes.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Describes the values in a training datafile: what kind of model they are, which game they
+/// were trained for and how
+#[derive(Debug, PartialEq, Clone, RustcDecodable, RustcEncodable)]
+pub struct Header {
+    pub version: u32,
+    pub model: Model,
+    pub rules: RuleSet,
+    pub pits: usize,
+    /// Every session of training, with the algorithm and hyperparameters each used. The last
+    /// may still be in progress when checkpointing.
+    pub history: TrainingHistory,
+}
+
+impl Header {
+    /// A header for `model` values trained on the board `state` is played on
+    pub fn new(model: Model, state: &GameState, history: TrainingHistory) -> Header {
+        Header { version: FORMAT_VERSION, model: model, rules: state.rules(),
+                 pits: state.pits(), history: history }
+    }
+
+    /// Check the values in the datafile at `path` were trained for the board `state` is on
+    pub fn check(&self, path: &str, state: &GameState) -> Result<(), String> {
+        if self.rules != state.rules() || self.pits != state.pits() {
+            return Err(format!("{} was trained for {} pit {}, not {} pit {}", path, self.pits,
+                               self.rules, state.pits(), state.rules()));
+        }
+        Ok(())
+    }
+}
+
+/// Write `bytes` to the file at `path`, replacing whatever was there. They're written to a
+/// temporary file first and renamed into place, so an interrupted save never leaves a
+/// half-written datafile behind.
+pub fn write_atomically(bytes: &[u8], path: &str) -> Result<(), String> {
+    write_atomically_with(path, |out| out.write_all(bytes))
+}
+
+/// Like `write_atomically`, but with whatever `write` writes, buffered, for files too big to
+/// build in memory first
+pub fn write_atomically_with<F>(path: &str, write: F) -> Result<(), String>
+    where F: FnOnce(&mut Write) -> io::Result<()>
+{
+    let temp = format!("{}.tmp", path);
+    {
+        let f = try!(File::create(&temp).map_err(|e| format!("can't create {}: {}", temp, e)));
+        let mut out = BufWriter::new(f);
+        try!(write(&mut out).and_then(|_| out.flush()).and_then(|_| out.get_ref().sync_all())
+             .map_err(|e| format!("can't write {}: {}", temp, e)));
+    }
+    fs::rename(&temp, path).map_err(|e| format!("can't replace {}: {}", path, e))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    let mut f = try!(File::open(path).map_err(|e| format!("can't open {}: {}", path, e)));
+    let mut encoded = Vec::new();
+    try!(f.read_to_end(&mut encoded).map_err(|e| format!("can't read {}: {}", path, e)));
+    Ok(encoded)
+}
+
+/// Write `value` to the file at `path` with nothing to describe it, as for endgame databases
+pub fn save<T: Encodable>(value: &T, path: &str) -> Result<(), String> {
+    let encoded: Vec<u8> = try!(encode(value, SizeLimit::Infinite).map_err(|e| e.to_string()));
+    write_atomically(&encoded, path)
+}
+
+/// Read back a value written by `save`
+pub fn load<T: Decodable>(path: &str) -> Result<T, String> {
+    let encoded = try!(read(path));
+    decode(&encoded).map_err(|e| format!("{} is not a valid datafile: {}", path, e))
+}
+
+/// `MAGIC` followed by `header`, as every training datafile starts
+pub fn encode_header(header: &Header) -> Result<Vec<u8>, String> {
+    let mut encoded = MAGIC.to_vec();
+    encoded.extend(try!(encode(header, SizeLimit::Infinite).map_err(|e| e.to_string())));
+    Ok(encoded)
+}
+
+/// Write trained `values` to the training datafile at `path`, after `MAGIC` and `header`
+pub fn save_values<T: Encodable>(values: &T, header: &Header, path: &str) -> Result<(), String> {
+    let mut encoded = try!(encode_header(header));
+    encoded.extend(try!(encode(values, SizeLimit::Infinite).map_err(|e| e.to_string())));
+    write_atomically(&encoded, path)
+}
+
+/// Read and validate the header at the start of the training datafile at `path` from `input`,
+/// leaving it at the encoded values that follow
+fn read_header<R: Read>(path: &str, input: &mut R) -> Result<Header, String> {
+    let mut magic = [0; 8];
+    if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(format!("{} has no header, so it's either not a training datafile or one \
+                            from before they had headers. Add one with `mancala migrate {}`.",
+                           path, path));
+    }
+    let header: Header = try!(decode_from(input, SizeLimit::Infinite)
+                              .map_err(|e| format!("{} has a corrupt header: {}", path, e)));
+    if header.version > FORMAT_VERSION {
+        return Err(format!("{} is format version {}, but this version of mancala only reads up \
+                            to {}", path, header.version, FORMAT_VERSION));
+    }
+    Ok(header)
+}
+
+/// Read and validate the header at the start of an `encoded` training datafile from `path`,
+/// returning it with the encoded values that follow
+pub fn decode_header<'a>(path: &str, encoded: &'a [u8]) -> Result<(Header, &'a [u8]), String> {
+    let mut rest = encoded;
+    let header = try!(read_header(path, &mut rest));
+    Ok((header, rest))
+}
+
+/// Open the training datafile at `path` and read its header, leaving the values unread
+pub fn open_values(path: &str) -> Result<(Header, BufReader<File>), String> {
+    let f = try!(File::open(path).map_err(|e| format!("can't open {}: {}", path, e)));
+    let mut input = BufReader::new(f);
+    let header = try!(read_header(path, &mut input));
+    Ok((header, input))
+}
+
+/// Decode the values that follow the header from `input`, opened by `open_values`
+pub fn decode_values<T: Decodable>(path: &str, input: &mut BufReader<File>) -> Result<T, String> {
+    decode_from(input, SizeLimit::Infinite)
+        .map_err(|e| format!("{} is not a valid datafile: {}", path, e))
+}
+
+/// Read just the header of the training datafile at `path`
+pub fn load_header(path: &str) -> Result<Header, String> {
+    open_values(path).map(|(header, _)| header)
+}
+
+/// Read back values of the kind `model` written by `save_values`, with their header
+pub fn load_values<T: Decodable>(path: &str, model: Model) -> Result<(Header, T), String> {
+    let (header, mut input) = try!(open_values(path));
+    if header.model != model {
+        return Err(format!("{} holds {:?} values, not {:?}", path, header.model, model));
+    }
+    let values = try!(decode_values(path, &mut input));
+    Ok((header, values))
+}
+
+/// Saves a value function every so many episodes of a long training run, to datafiles named
+/// after the training datafile and the episodes played so far over every session. Only the
+/// most recent few are kept, including any left by earlier sessions.
+pub struct Checkpoints {
+    path: String,
+    every: usize,
+    keep: usize,
+    /// Written with every checkpoint, its last session updated with the episodes so far
+    header: Header,
+    /// Episodes played in the sessions before this one
+    previous: usize,
+    /// Every checkpoint on disk, oldest first
+    saved: VecDeque<String>,
+    /// The checkpoint this session saved last
+    last: Option<String>,
+}
+
+/// The episodes of the checkpoints of the training datafile at `path` already on disk, oldest
+/// first
+fn existing_checkpoints(path: &str) -> Vec<usize> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.checkpoint-", path.file_name().unwrap_or_default().to_string_lossy());
+    let mut found: Vec<usize> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with(&prefix) { name[prefix.len()..].parse().ok() } else { None }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    found.sort();
+    found
+}
+
+impl Checkpoints {
+    /// Checkpoints for the session that's last in `header`'s history
+    pub fn new(path: &str, every: usize, keep: usize, header: Header) -> Checkpoints {
+        let previous = header.history.episodes() -
+            header.history.sessions.last().map_or(0, |session| session.episodes);
+        let mut checkpoints = Checkpoints { path: path.to_string(), every: every, keep: keep,
+                                            header: header, previous: previous,
+                                            saved: VecDeque::new(), last: None };
+        for episodes in existing_checkpoints(path) {
+            let saved = checkpoints.path(episodes);
+            checkpoints.saved.push_back(saved);
+        }
+        checkpoints
+    }
+
+    /// Where the checkpoint after `episodes` episodes over every session goes
+    pub fn path(&self, episodes: usize) -> String {
+        format!("{}.checkpoint-{}", self.path, episodes)
+    }
+
+    /// Called after every episode with the number played this session, saving a checkpoint
+    /// when one is due
+    pub fn episode_done(&mut self, values: &ValueFunction, episodes: usize) {
+        if self.every > 0 && (self.previous + episodes) % self.every == 0 {
+            self.save(values, episodes);
+        }
+    }
+
+    /// Save a checkpoint now, after `episodes` episodes this session, dropping the oldest if
+    /// there are too many. Failing to save is reported but doesn't stop training.
+    pub fn save(&mut self, values: &ValueFunction, episodes: usize) {
+        let path = self.path(self.previous + episodes);
+        if self.last.as_ref() == Some(&path) {
+            return;
+        }
+        if let Some(session) = self.header.history.sessions.last_mut() {
+            session.episodes = episodes;
+        }
+        if let Err(e) = values.save(&path, &self.header) {
+            println!("Failed to save checkpoint: {}", e);
+            return;
+        }
+        info!("Saved checkpoint {}", path);
+        self.last = Some(path.clone());
+        self.saved.retain(|saved| *saved != path);
+        self.saved.push_back(path);
+        while self.saved.len() > self.keep {
+            let old = self.saved.pop_front().unwrap();
+            if let Err(e) = fs::remove_file(&old) {
+                println!("Failed to remove old checkpoint {}: {}", old, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::path::Path;
+    use ::learning::{Algorithm, Session, TrainingHistory, TrainingParams, Traces};
+    use ::mancala::{GameState, RuleSet};
+    use ::value::Model;
+
+    fn history() -> TrainingHistory {
+        let params = TrainingParams { algorithm: Algorithm::Td, epsilon: 0.1, learning_rate: 0.1,
+                                      discount_factor: 1.0, lambda: 0.0,
+                                      traces: Traces::Accumulating };
+        TrainingHistory { sessions: vec![Session { date: 0, episodes: 0, params: params }] }
+    }
+
+    #[test]
+    fn test_header() {
+        let state = GameState::with_board(4, 3, RuleSet::kalah());
+        let header = Header::new(Model::Linear, &state, history());
+        let path = env::temp_dir().join("mancala-test-header.dat");
+        let path = path.to_str().unwrap();
+        save_values(&vec![1.0f64, 2.0], &header, path).unwrap();
+        assert_eq!(load_header(path).unwrap(), header);
+        assert_eq!(load_values::<Vec<f64>>(path, Model::Linear).unwrap(),
+                   (header.clone(), vec![1.0, 2.0]));
+        assert!(load_values::<Vec<f64>>(path, Model::Network).unwrap_err()
+                .contains("holds Linear values"));
+        assert!(header.check(path, &state).is_ok());
+        assert!(header.check(path, &GameState::with_board(6, 3, RuleSet::kalah())).is_err());
+        assert!(header.check(path, &GameState::with_board(4, 3, RuleSet::oware())).is_err());
+
+        // Newer formats and headerless datafiles are refused
+        let newer = Header { version: FORMAT_VERSION + 1, ..header.clone() };
+        save_values(&vec![1.0f64], &newer, path).unwrap();
+        assert!(load_header(path).unwrap_err().contains("format version"));
+        save(&vec![1.0f64], path).unwrap();
+        assert!(load_header(path).unwrap_err().contains("no header"));
+        write_atomically(b"MANC", path).unwrap();
+        assert!(load_header(path).unwrap_err().contains("no header"));
+
+        // Only the header is read, whatever follows it
+        let mut encoded = encode_header(&header).unwrap();
+        encoded.extend(&[0xff; 3]);
+        write_atomically(&encoded, path).unwrap();
+        assert_eq!(load_header(path).unwrap(), header);
+        assert!(load_values::<Vec<f64>>(path, Model::Linear).unwrap_err()
+                .contains("not a valid datafile"));
+    }
+
+    /// Clear out checkpoints left by earlier test runs
+    fn remove_checkpoints(path: &str) {
+        for episodes in existing_checkpoints(path) {
+            fs::remove_file(format!("{}.checkpoint-{}", path, episodes)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let path = env::temp_dir().join("mancala-test-checkpoints.dat");
+        remove_checkpoints(path.to_str().unwrap());
+        let header = Header::new(Model::Table, &GameState::new(1), history());
+        let mut checkpoints = Checkpoints::new(path.to_str().unwrap(), 5, 2, header);
+        let mut values: HashMap<GameState, f64> = HashMap::new();
+        for episodes in 1..21 {
+            values.insert(GameState::new(episodes as u8), 1.0);
+            checkpoints.episode_done(&values, episodes);
+        }
+        // Saving again at the same point doesn't count as another checkpoint
+        checkpoints.save(&values, 20);
+        for &(episodes, kept) in [(5, false), (10, false), (15, true), (20, true)].iter() {
+            assert_eq!(Path::new(&checkpoints.path(episodes)).exists(), kept, "{}", episodes);
+        }
+        let (header, saved): (Header, HashMap<GameState, f64>) =
+            load_values(&checkpoints.path(15), Model::Table).unwrap();
+        assert_eq!(saved.len(), 15);
+        // The session in progress is saved with the episodes played so far
+        assert_eq!(header.history.episodes(), 15);
+        assert!(!Path::new(&format!("{}.tmp", checkpoints.path(20))).exists());
+    }
+
+    #[test]
+    fn test_checkpoints_resumed() {
+        let path = env::temp_dir().join("mancala-test-checkpoints-resumed.dat");
+        let path = path.to_str().unwrap();
+        remove_checkpoints(path);
+        let mut header = Header::new(Model::Table, &GameState::new(1), history());
+        let values: HashMap<GameState, f64> = HashMap::new();
+        for session in 0..2 {
+            let mut checkpoints = Checkpoints::new(path, 4, 2, header.clone());
+            for episodes in 1..11 {
+                checkpoints.episode_done(&values, episodes);
+            }
+            checkpoints.save(&values, 10);
+            // Checkpoints are numbered over every session, and those of earlier sessions are
+            // dropped like any other
+            let expected: Vec<usize> = if session == 0 { vec![8, 10] } else { vec![16, 20] };
+            assert_eq!(existing_checkpoints(path), expected);
+            header.history.sessions.last_mut().unwrap().episodes = 10;
+            header.history.sessions.push(history().sessions[0].clone());
+        }
+        let (saved, _): (Header, HashMap<GameState, f64>) =
+            load_values(&format!("{}.checkpoint-20", path), Model::Table).unwrap();
+        assert_eq!(saved.history.episodes(), 20);
+        assert_eq!(saved.history.sessions.len(), 2);
+        remove_checkpoints(path);
+    }
+}
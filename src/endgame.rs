@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use ::mancala::{GameState, PlayerTurn, RuleSet, Variant};
+use ::packed_actions::Action;
+
+/// Exact values of every Kalah position with up to `max_seeds` seeds left in the houses.
+///
+/// Under Kalah rules what happens to the seeds still in play doesn't depend on how many are
+/// already in the stores, so positions are stored with empty stores, from the point of view of
+/// the player to move, and valued as how many more of the remaining seeds that player ends up
+/// with than their opponent under perfect play.
+#[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct EndgameDb {
+    pub pits: usize,
+    pub rules: RuleSet,
+    pub max_seeds: usize,
+    values: HashMap<GameState, i8>,
+}
+
+fn board_seeds(state: &GameState) -> usize {
+    let pits = state.pits();
+    let houses = state.houses();
+    houses[..pits].iter().chain(&houses[pits+1..2*pits+1]).map(|&h| h as usize).sum()
+}
+
+/// `state` seen by the player to move, with both stores emptied. Under Kalah rules every
+/// position with the same key plays out the same way.
+pub fn key(state: &GameState) -> GameState {
+    let view = state.from_perspective(state.to_move());
+    let pits = view.pits();
+    let houses = view.houses();
+    GameState::from_sides(&houses[..pits], 0, &houses[pits+1..2*pits+1], 0,
+                          view.rules(), PlayerTurn::P1).unwrap()
+}
+
+/// Seed-weighted distance travelled along each side. A move that keeps every seed on the board
+/// can't reach its own store, so only shuffles seeds further along the mover's side and always
+/// raises this; solving each seed count in decreasing order of it means a position's successors
+/// are always solved first.
+fn progress(state: &GameState) -> usize {
+    let pits = state.pits();
+    let houses = state.houses();
+    (0..pits).map(|i| i * (houses[i] as usize + houses[pits + 1 + i] as usize)).sum()
+}
+
+/// Final store difference for the player to move in a finished game, once the board is swept
+pub fn swept_margin(state: &GameState) -> i32 {
+    let view = state.from_perspective(state.to_move());
+    let pits = view.pits();
+    let houses = view.houses();
+    let stores = houses[pits] as i32 - houses[2 * pits + 1] as i32;
+    let ours = houses[..pits].iter().map(|&h| h as i32).sum::<i32>();
+    let theirs = houses[pits+1..2*pits+1].iter().map(|&h| h as i32).sum::<i32>();
+    if state.rules().sweep_to_owner {
+        stores + ours - theirs
+    } else {
+        // Everything goes to our opponent, who made the last move
+        stores - ours - theirs
+    }
+}
+
+/// Every way of spreading `seeds` over `houses` houses
+fn distributions(houses: usize, seeds: usize) -> Vec<Vec<u8>> {
+    if houses == 1 {
+        return vec![vec![seeds as u8]];
+    }
+    let mut all = Vec::new();
+    for first in 0..seeds + 1 {
+        for mut rest in distributions(houses - 1, seeds - first) {
+            rest.insert(0, first as u8);
+            all.push(rest);
+        }
+    }
+    all
+}
+
+impl EndgameDb {
+    /// Solve every position on a `pits` board with up to `max_seeds` seeds in play, fewest seeds
+    /// first so that captures and sowing into the stores always lead to solved positions.
+    pub fn solve(pits: usize, rules: RuleSet, max_seeds: usize) -> EndgameDb {
+        assert!(rules.variant == Variant::Kalah, "only Kalah endgames can be solved");
+        assert!(max_seeds <= ::std::i8::MAX as usize, "too many seeds to solve");
+        let mut db = EndgameDb { pits: pits, rules: rules, max_seeds: max_seeds,
+                                 values: HashMap::new() };
+        for seeds in 0..max_seeds + 1 {
+            let mut level: Vec<GameState> = distributions(2 * pits, seeds).iter()
+                .map(|houses| GameState::from_sides(&houses[..pits], 0, &houses[pits..], 0,
+                                                    rules, PlayerTurn::P1).unwrap())
+                .filter(|state| !state.is_ended())
+                .collect();
+            level.sort_by(|a, b| progress(b).cmp(&progress(a)));
+            for state in level {
+                let value = db.solve_position(&state);
+                db.values.insert(state, value as i8);
+            }
+            info!("Solved endgames with {} seeds, {} positions so far", seeds, db.values.len());
+        }
+        db
+    }
+
+    /// The best margin the player to move can get from a position whose successors are solved
+    fn solve_position(&self, state: &GameState) -> i32 {
+        state.gen_actions()
+            .map(|action| -self.margin(&{ let mut next = *state; next.apply(action); next })
+                 .expect("successor of an endgame position should already be solved"))
+            .max()
+            .unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Does the database cover `state`?
+    pub fn covers(&self, state: &GameState) -> bool {
+        state.pits() == self.pits && state.rules() == self.rules
+            && board_seeds(state) <= self.max_seeds
+    }
+
+    /// Final store difference for the player to move under perfect play from here, if `state`
+    /// is covered
+    pub fn margin(&self, state: &GameState) -> Option<i32> {
+        if !self.covers(state) {
+            return None;
+        }
+        if state.is_ended() {
+            return Some(swept_margin(state));
+        }
+        let view = state.from_perspective(state.to_move());
+        let pits = view.pits();
+        let stores = view.houses()[pits] as i32 - view.houses()[2 * pits + 1] as i32;
+        self.values.get(&key(state)).map(|&remaining| stores + remaining as i32)
+    }
+
+    /// The action with the best final margin for the player to move, and that margin, if
+    /// `state` is covered
+    pub fn best_action(&self, state: &GameState) -> Option<(Action, i32)> {
+        if !self.covers(state) || state.is_ended() {
+            return None;
+        }
+        let mut best: Option<(Action, i32)> = None;
+        for action in state.gen_actions() {
+            let mut next = *state;
+            next.apply(action);
+            let margin = match self.margin(&next) {
+                Some(margin) => -margin,
+                None => return None,
+            };
+            if best.map_or(true, |(_, best_margin)| margin > best_margin) {
+                best = Some((action, margin));
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, PlayerTurn, RuleSet};
+    use ::notation::Position;
+    use ::search::{SearchPlayer, StoreDifference, MAX_SEARCH_DEPTH};
+
+    /// Final margin for the player to move by plain exhaustive search
+    fn brute_force(state: &GameState) -> i32 {
+        if state.is_ended() {
+            return swept_margin(state);
+        }
+        state.gen_actions()
+            .map(|action| { let mut next = *state; next.apply(action); -brute_force(&next) })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_distributions() {
+        assert_eq!(distributions(3, 0), vec![vec![0, 0, 0]]);
+        assert_eq!(distributions(2, 2), vec![vec![0, 2], vec![1, 1], vec![2, 0]]);
+        assert_eq!(distributions(8, 4).len(), 330);
+    }
+
+    #[test]
+    fn test_solve() {
+        let db = EndgameDb::solve(4, RuleSet::kalah(), 7);
+        let positions = ["1,0,1,0/3/5,0,0,0/4 1 kalah",
+                         "0,2,0,1/10/1,0,1,0/7 2 kalah",
+                         "1,1,1,1/0/0,0,1,0/0 1 kalah",
+                         "0,0,0,1/0/0,0,0,0/0 2 kalah"];
+        for notation in positions.iter() {
+            let state = notation.parse::<Position>().unwrap().0;
+            assert_eq!(db.margin(&state), Some(brute_force(&state)), "{}", notation);
+        }
+        let winning = "1,0,1,0/3/5,0,0,0/4 1 kalah".parse::<Position>().unwrap().0;
+        assert_eq!(db.best_action(&winning).map(|(action, _)| action.notation()),
+                   Some("2".to_string()));
+
+        // Too many seeds, or the wrong rules, aren't covered
+        assert_eq!(db.margin(&GameState::with_board(4, 2, RuleSet::kalah())), None);
+        let mut rules = RuleSet::kalah();
+        rules.sweep_to_owner = false;
+        assert_eq!(db.margin(&GameState::with_board(4, 0, rules)), None);
+    }
+
+    #[test]
+    fn test_sweep_to_mover() {
+        let mut rules = RuleSet::kalah();
+        rules.sweep_to_owner = false;
+        let db = EndgameDb::solve(4, rules, 4);
+        let state = GameState::from_sides(&[1,0,0,1], 2, &[0,1,1,0], 1, rules, PlayerTurn::P2).unwrap();
+        assert_eq!(db.margin(&state), Some(brute_force(&state)));
+    }
+
+    #[test]
+    fn test_search_probe() {
+        let db = EndgameDb::solve(4, RuleSet::kalah(), 6);
+        let state = "0,2,1,0/9/0,1,1,1/10 1 kalah".parse::<Position>().unwrap().0;
+        let mut player = SearchPlayer::new(1, Box::new(StoreDifference)).with_endgame(db);
+        let mut deep = SearchPlayer::new(MAX_SEARCH_DEPTH, Box::new(StoreDifference));
+        // With the database a one ply search plays as well as searching to the end
+        assert_eq!(player.search(&state).1, deep.search(&state).1);
+    }
+}
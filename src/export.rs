@@ -0,0 +1,207 @@
+use std::io::{self, BufRead, Write};
+use rustc_serialize::json;
+use ::mancala::{GameState, PlayerTurn, RuleSet};
+use ::value::Table;
+
+/// How tables are written out for other tools: comma separated with a header line, or one JSON
+/// object a line
+#[derive(Debug, Eq, PartialEq, Copy, Clone, RustcDecodable)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+/// A state, its value and how many times training played into it, as exported. States are
+/// afterstates, seen from the perspective of the player who just moved, so "ours" is the first
+/// side.
+#[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+struct Row {
+    houses: Vec<u8>,
+    store: u8,
+    opponent_houses: Vec<u8>,
+    opponent_store: u8,
+    /// 1 or 2
+    to_move: u8,
+    value: f64,
+    visits: u32,
+}
+
+impl Row {
+    fn new(state: &GameState, value: f64, visits: u32) -> Row {
+        let pits = state.pits();
+        let houses = state.houses();
+        Row {
+            houses: houses[..pits].to_vec(),
+            store: houses[pits],
+            opponent_houses: houses[pits+1..2*pits+1].to_vec(),
+            opponent_store: houses[2*pits+1],
+            to_move: if state.to_move() == PlayerTurn::P1 { 1 } else { 2 },
+            value: value,
+            visits: visits,
+        }
+    }
+
+    /// The state this row describes, if it's on a board of `pits` houses a side
+    fn state(&self, rules: RuleSet, pits: usize) -> Result<GameState, String> {
+        if self.houses.len() != pits {
+            return Err(format!("{} houses a side, not {}", self.houses.len(), pits));
+        }
+        let to_move = match self.to_move {
+            1 => PlayerTurn::P1,
+            2 => PlayerTurn::P2,
+            other => return Err(format!("no player {} to move", other)),
+        };
+        GameState::from_sides(&self.houses, self.store, &self.opponent_houses,
+                              self.opponent_store, rules, to_move)
+    }
+}
+
+/// The CSV header line for boards of `pits` houses a side
+fn csv_header(pits: usize) -> String {
+    let mut columns: Vec<String> = (1..pits + 1).map(|i| format!("house_{}", i)).collect();
+    columns.push("store".to_string());
+    columns.extend((1..pits + 1).map(|i| format!("opponent_house_{}", i)));
+    columns.push("opponent_store".to_string());
+    columns.push("to_move".to_string());
+    columns.push("value".to_string());
+    columns.push("visits".to_string());
+    columns.join(",")
+}
+
+/// Write a row per state in `table`, for boards of `pits` houses a side, in order of state
+pub fn export(table: &Table, pits: usize, format: Format, out: &mut Write) -> io::Result<()> {
+    let mut states: Vec<&GameState> = table.values.keys().collect();
+    states.sort_by(|a, b| (a.houses(), a.to_move() == PlayerTurn::P2)
+                   .cmp(&(b.houses(), b.to_move() == PlayerTurn::P2)));
+    if format == Format::Csv {
+        try!(writeln!(out, "{}", csv_header(pits)));
+    }
+    for state in states {
+        let row = Row::new(state, table.values[state], table.visits(state));
+        match format {
+            Format::Csv => {
+                let counts: Vec<String> = row.houses.iter().chain(Some(&row.store))
+                    .chain(&row.opponent_houses).chain(Some(&row.opponent_store))
+                    .chain(Some(&row.to_move))
+                    .map(|count| count.to_string())
+                    .collect();
+                try!(writeln!(out, "{},{},{}", counts.join(","), row.value, row.visits));
+            }
+            Format::Jsonl => {
+                let line = try!(json::encode(&row)
+                                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+                try!(writeln!(out, "{}", line));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a CSV line of counts, a value and visits, in the order of `csv_header`
+fn parse_csv(line: &str, pits: usize) -> Result<Row, String> {
+    let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+    if fields.len() != 2 * pits + 5 {
+        return Err(format!("{} fields, not {}", fields.len(), 2 * pits + 5));
+    }
+    let mut counts = Vec::new();
+    for field in &fields[..2 * pits + 3] {
+        counts.push(try!(field.parse::<u8>()
+                         .map_err(|e| format!("bad count {:?}: {}", field, e))));
+    }
+    let value = try!(fields[2 * pits + 3].parse::<f64>()
+                     .map_err(|e| format!("bad value {:?}: {}", fields[2 * pits + 3], e)));
+    let visits = try!(fields[2 * pits + 4].parse::<u32>()
+                      .map_err(|e| format!("bad visits {:?}: {}", fields[2 * pits + 4], e)));
+    Ok(Row {
+        houses: counts[..pits].to_vec(),
+        store: counts[pits],
+        opponent_houses: counts[pits+1..2*pits+1].to_vec(),
+        opponent_store: counts[2*pits+1],
+        to_move: counts[2*pits+2],
+        value: value,
+        visits: visits,
+    })
+}
+
+/// Read a table written by `export`, or by anything else writing the same rows, for boards of
+/// `pits` houses a side played by `rules`
+pub fn import(input: &mut BufRead, format: Format, rules: RuleSet,
+              pits: usize) -> Result<Table, String> {
+    let mut table = Table::with_capacity(0);
+    for (number, line) in input.lines().enumerate() {
+        let line = try!(line.map_err(|e| e.to_string()));
+        if line.trim().is_empty() {
+            continue;
+        }
+        if number == 0 && format == Format::Csv {
+            if line.trim() != csv_header(pits) {
+                return Err(format!("line 1: expected the header {} for {} pits", csv_header(pits),
+                                   pits));
+            }
+            continue;
+        }
+        let row = try!(match format {
+            Format::Csv => parse_csv(&line, pits),
+            Format::Jsonl => json::decode::<Row>(&line).map_err(|e| e.to_string()),
+        }.and_then(|row| {
+            if row.value.is_finite() {
+                Ok(row)
+            } else {
+                Err(format!("value {} isn't a number", row.value))
+            }
+        }).map_err(|e| format!("line {}: {}", number + 1, e)));
+        let state = try!(row.state(rules, pits)
+                         .map_err(|e| format!("line {}: {}", number + 1, e)));
+        table.values.insert(state, row.value);
+        if row.visits > 0 {
+            table.visits.insert(state, row.visits);
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, RuleSet};
+    use ::value::Table;
+
+    #[test]
+    fn test_export_import() {
+        let start = GameState::with_board(4, 3, RuleSet::kalah());
+        let mut table = Table::with_capacity(4);
+        for (i, action) in start.gen_actions().enumerate() {
+            table.values.insert(start.afterstate(action), i as f64 / 4.0);
+            if i > 0 {
+                table.visits.insert(start.afterstate(action), 10 * i as u32);
+            }
+        }
+        for &format in [Format::Csv, Format::Jsonl].iter() {
+            let mut exported = Vec::new();
+            export(&table, 4, format, &mut exported).unwrap();
+            let lines = String::from_utf8(exported.clone()).unwrap().lines().count();
+            assert_eq!(lines, table.values.len() + if format == Format::Csv { 1 } else { 0 });
+            let imported = import(&mut &exported[..], format, RuleSet::kalah(), 4).unwrap();
+            assert_eq!(imported.values.len(), table.values.len());
+            // The JSON parser can be a bit out in the last place
+            for (state, value) in &table.values {
+                assert!((imported.values[state] - value).abs() < 1e-12, "{:?}", format);
+            }
+            assert_eq!(imported.visits, table.visits);
+        }
+
+        let mut exported = Vec::new();
+        export(&table, 4, Format::Csv, &mut exported).unwrap();
+        let text = String::from_utf8(exported).unwrap();
+        assert!(text.starts_with("house_1,house_2,house_3,house_4,store,opponent_house_1,"));
+        // Rows for another board size are refused, naming the line
+        assert!(import(&mut text.as_bytes(), Format::Csv, RuleSet::kalah(), 6).is_err());
+        let bad = format!("{}\n1,2,3\n", csv_header(4));
+        assert_eq!(import(&mut bad.as_bytes(), Format::Csv, RuleSet::kalah(), 4).unwrap_err(),
+                   "line 2: 3 fields, not 13");
+        let bad = "{\"houses\":[1,2],\"store\":0,\"opponent_houses\":[0,0],\"opponent_store\":0,\
+                   \"to_move\":2,\"value\":0.5,\"visits\":1}";
+        assert_eq!(import(&mut bad.as_bytes(), Format::Jsonl, RuleSet::kalah(), 4).unwrap_err(),
+                   "line 1: 2 houses a side, not 4");
+    }
+}
@@ -1,5 +1,9 @@
-use std::collections::HashMap;
-use super::player::{Player, AIPlayer};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use libc;
+use ::datafile::Checkpoints;
+use super::player::{side_index, Player, AIPlayer};
+use super::value::ValueFunction;
 
 fn dump_counter_stats(lens: &Vec<usize>, header_only: bool) {
 
@@ -25,66 +29,237 @@ fn dump_counter_stats(lens: &Vec<usize>, header_only: bool) {
     println!("");
 }
 
-use ::mancala::GameState;
+use ::mancala::{GameState, Outcome, PlayerTurn};
 
-pub fn sarsa_loop(values: &mut HashMap<GameState, f64>,
+/// How a state's eligibility trace grows when it's visited again before it has decayed away
+#[derive(Debug, Eq, PartialEq, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub enum Traces {
+    /// Add one to the trace
+    Accumulating,
+    /// Reset the trace to one
+    Replacing,
+}
+
+/// Traces below this are dropped rather than decayed forever
+const MIN_TRACE: f64 = 1e-4;
+
+/// Eligibility traces for TD(lambda): how much credit each state visited this game gets for
+/// the latest TD error. With lambda of zero only the most recent state gets any, which is plain
+/// TD(0).
+pub struct EligibilityTraces {
+    lambda: f64,
+    kind: Traces,
+    traces: HashMap<GameState, f64>,
+}
+
+impl EligibilityTraces {
+    pub fn new(lambda: f64, kind: Traces) -> EligibilityTraces {
+        EligibilityTraces { lambda: lambda, kind: kind, traces: HashMap::new() }
+    }
+
+    /// Decay every trace a step and mark `state` as just visited
+    pub fn visit(&mut self, state: GameState, discount_factor: f64) {
+        let decay = self.lambda * discount_factor;
+        for trace in self.traces.values_mut() {
+            *trace *= decay;
+        }
+        self.traces.retain(|_, trace| *trace >= MIN_TRACE);
+        let trace = self.traces.entry(state).or_insert(0.0);
+        match self.kind {
+            Traces::Accumulating => *trace += 1.0,
+            Traces::Replacing => *trace = 1.0,
+        }
+    }
+
+    /// Move every traced state's value towards the TD error `delta` in proportion to its trace
+    pub fn update(&self, values: &mut ValueFunction, learning_rate: f64, delta: f64) {
+        for (state, trace) in &self.traces {
+            let target = values.value(state) + delta * trace;
+            values.update(state, target, learning_rate);
+        }
+    }
+}
+
+/// Which update rule to learn with. Every algorithm plays the same epsilon-greedy self-play
+/// games; they differ in what each afterstate's value is moved towards.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub enum Algorithm {
+    /// On-policy TD(lambda): towards the value of the next state actually reached
+    Td,
+    /// Off-policy: towards the best afterstate available on our next turn, whatever we then play
+    QLearning,
+    /// Monte Carlo: towards the final result, for every time a state was played
+    EveryVisit,
+    /// Monte Carlo: towards the final result, only for the first time a state was played
+    FirstVisit,
+}
+
+/// Everything about how to train besides the board and how long for
+#[derive(Debug, PartialEq, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub struct TrainingParams {
+    pub algorithm: Algorithm,
+    pub epsilon: f64,
+    pub learning_rate: f64,
+    pub discount_factor: f64,
+    pub lambda: f64,
+    pub traces: Traces,
+}
+
+/// One run of training: when it started, how many games it played and how it learned from them
+#[derive(Debug, PartialEq, Clone, RustcDecodable, RustcEncodable)]
+pub struct Session {
+    pub date: u64,
+    pub episodes: usize,
+    pub params: TrainingParams,
+}
+
+/// Every session of training that went into a value function, oldest first. It's kept in the
+/// header of the training datafile.
+#[derive(Debug, PartialEq, Clone, RustcDecodable, RustcEncodable)]
+pub struct TrainingHistory {
+    pub sessions: Vec<Session>,
+}
+
+impl TrainingHistory {
+    pub fn new() -> TrainingHistory {
+        TrainingHistory { sessions: Vec::new() }
+    }
+
+    /// Games played over every session
+    pub fn episodes(&self) -> usize {
+        self.sessions.iter().map(|session| session.episodes).sum()
+    }
+}
+
+/// Monte Carlo update of the afterstates one side played, given the reward they finished with
+fn monte_carlo_update(values: &mut ValueFunction, afterstates: &[GameState],
+                      reward: f64, params: &TrainingParams) {
+    let mut seen = HashSet::new();
+    for (i, &state) in afterstates.iter().enumerate() {
+        if !seen.insert(state) && params.algorithm == Algorithm::FirstVisit {
+            continue;
+        }
+        let steps_to_end = (afterstates.len() - 1 - i) as i32;
+        let target = reward * params.discount_factor.powi(steps_to_end);
+        values.update(&state, target, params.learning_rate);
+    }
+}
+
+/// Set when we're asked to stop, so training can finish the episode it's on and save
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Have Ctrl-C and SIGTERM stop training at the end of the current episode rather than killing
+/// the process, so what has been learned can still be saved
+pub fn stop_on_interrupt() {
+    let handler = interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Learn `values` from `episodes` games of self-play from `starting_state`, saving checkpoints
+/// along the way if given some. Returns how many episodes were played, which is fewer than asked
+/// for when interrupted.
+pub fn training_loop(values: &mut ValueFunction,
               starting_state: GameState,
-              epsilon: f64,
-              learning_rate: f64,
-              discount_factor: f64,
-              episodes: usize) {
+              params: &TrainingParams,
+              episodes: usize,
+              mut checkpoints: Option<&mut Checkpoints>) -> usize {
     let print_rate = 1000;
     let mut game_lengths = Vec::with_capacity(print_rate);
+    let (learning_rate, discount_factor) = (params.learning_rate, params.discount_factor);
     dump_counter_stats(&game_lengths, true);
     
     for episode in 0..episodes {
-        let mut current_player = AIPlayer::new(starting_state);
-        let mut opposing_player = {
-            let mut opp_starting_state = starting_state.clone();
-            opp_starting_state.swap_board();
-            AIPlayer::new(opp_starting_state)
-        };
+        let mut state = starting_state;
+        let mut current_player = AIPlayer::new(state.to_move(), &state)
+            .with_traces(params.lambda, params.traces);
+        let mut opposing_player = AIPlayer::new(state.to_move().other(), &state)
+            .with_traces(params.lambda, params.traces);
         info!(">>>>>>>>>>>>>>>>>");
         let mut counter = 0;
+        // Oware positions can recur forever; a repeat ends the game with the board swept
+        let mut seen_states = HashSet::new();
+        // The afterstates each side has played this game, from their own point of view
+        let mut afterstates: [Vec<GameState>; 2] = [Vec::new(), Vec::new()];
         loop {
-            let players_turn = if counter % 2 == 0 { 1 } else { 2 };
-            info!("Turn {}, player {}'s turn", counter, players_turn);
-
-            let action = current_player.take_action(values, epsilon);
-            opposing_player.opponent_plays(action);
-
-            if current_player.curr_state.is_ended() {
-                info!("Game ended at state:\n{}", current_player.curr_state);
-                let (tie, curr_player_win) = {
-                    let mut copy = current_player.curr_state.clone();
-                    copy.finalize_game();
-                    let diff = copy.houses[6] as i32 - copy.houses[13] as i32;
-                    (diff == 0, diff > 0)
+            info!("Turn {}, player {:?}'s turn", counter, state.to_move());
+
+            let mover = state.to_move();
+            if params.algorithm == Algorithm::QLearning {
+                if let Some(&last) = afterstates[side_index(mover)].last() {
+                    let (_, best) = state.pick_action(0.0, values);
+                    values.update(&last, discount_factor * best, learning_rate);
+                }
+            }
+            let action = current_player.take_action(&state, values, params.epsilon);
+            state.apply(action);
+            opposing_player.opponent_plays(&state);
+            afterstates[side_index(mover)].push(current_player.current_state());
+            values.visit(&current_player.current_state());
+
+            let repeated = !seen_states.insert(state);
+            if state.is_ended() || repeated {
+                info!("Game ended at state:\n{}", state);
+                let outcome = state.final_outcome();
+                let outcome = if mover == PlayerTurn::P1 { outcome } else { outcome.reversed() };
+                let (tie, curr_player_win) = match outcome {
+                    Outcome::P1Win => (false, true),
+                    Outcome::P2Win => (false, false),
+                    Outcome::Tie => (true, false),
                 };
+                // A full step, which sets a table's values outright
                 if curr_player_win {
-                    values.insert(current_player.curr_state, 1.0);
-                    values.insert(opposing_player.curr_state, 0.0);
+                    values.update(&current_player.current_state(), 1.0, 1.0);
+                    values.update(&opposing_player.current_state(), 0.0, 1.0);
                 } else if !tie {
-                    values.insert(current_player.curr_state, 0.0);
-                    values.insert(opposing_player.curr_state, 1.0);
+                    values.update(&current_player.current_state(), 0.0, 1.0);
+                    values.update(&opposing_player.current_state(), 1.0, 1.0);
+                }
+                let mover_reward = if tie { 0.5 } else if curr_player_win { 1.0 } else { 0.0 };
+                let opponent = side_index(mover.other());
+                match params.algorithm {
+                    Algorithm::Td => {
+                        // The only reason this duplication has to happen here is because
+                        // we need to first set the terminal states to {1.0, 0.0}
+                        // otherwise we could move these four lines before the is_ended check
+                        // and remove the duplication
+                        debug!("TD Update for current player");
+                        current_player.td_update(values, learning_rate, discount_factor);
+                        debug!("TD Update for opposing player");
+                        opposing_player.td_update(values, learning_rate, discount_factor);
+                    }
+                    Algorithm::QLearning => {
+                        // The opponent never gets another turn to look ahead from, so their
+                        // last afterstate learns from the result directly
+                        if let Some(&last) = afterstates[opponent].last() {
+                            values.update(&last, discount_factor * (1.0 - mover_reward),
+                                          learning_rate);
+                        }
+                    }
+                    Algorithm::EveryVisit | Algorithm::FirstVisit => {
+                        monte_carlo_update(values, &afterstates[side_index(mover)],
+                                           mover_reward, params);
+                        monte_carlo_update(values, &afterstates[opponent],
+                                           1.0 - mover_reward, params);
+                    }
                 }
-                // The only reason this duplication has to happen here is because
-                // we need to first set the terminal states to {1.0, 0.0}
-                // otherwise we could move these four lines before the is_ended check
-                // and remove the duplication
-                debug!("TD Update for current player");
-                current_player.td_update(values, learning_rate, discount_factor);
-                debug!("TD Update for opposing player");
-                opposing_player.td_update(values, learning_rate, discount_factor);
 
                 counter += 1;
                 game_lengths.push(counter);
                 break;
             }
-            debug!("TD Update for current player");
-            current_player.td_update(values, learning_rate, discount_factor);
-            debug!("TD Update for opposing player");
-            opposing_player.td_update(values, learning_rate, discount_factor);
+            if params.algorithm == Algorithm::Td {
+                debug!("TD Update for current player");
+                current_player.td_update(values, learning_rate, discount_factor);
+                debug!("TD Update for opposing player");
+                opposing_player.td_update(values, learning_rate, discount_factor);
+            }
             counter += 1;
             std::mem::swap(&mut current_player, &mut opposing_player);
             info!(">>>>>>>>>>>>>>>>>");
@@ -93,7 +268,153 @@ pub fn sarsa_loop(values: &mut HashMap<GameState, f64>,
             dump_counter_stats(&game_lengths, false);
             game_lengths.clear();
         }
+        if let Some(ref mut checkpoints) = checkpoints {
+            checkpoints.episode_done(values, episode + 1);
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            println!("Interrupted after {} episodes", episode + 1);
+            if let Some(ref mut checkpoints) = checkpoints {
+                checkpoints.save(values, episode + 1);
+            }
+            dump_counter_stats(&game_lengths, false);
+            return episode + 1;
+        }
     }
     dump_counter_stats(&game_lengths, false);
+    episodes
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use ::mancala::{GameState, RuleSet};
+    use ::network::Network;
+    use ::value::{Table, DEFAULT_STATE_VAL};
+
+    #[test]
+    fn test_traces() {
+        let a = GameState::new(1);
+        let b = GameState::new(2);
+        let mut values: HashMap<GameState, f64> = HashMap::new();
+        let mut traces = EligibilityTraces::new(0.5, Traces::Accumulating);
+        traces.visit(a, 1.0);
+        traces.visit(b, 1.0);
+        traces.update(&mut values, 0.1, 1.0);
+        assert_eq!(values[&b], 0.6);
+        assert_eq!(values[&a], 0.55);
+
+        // Revisiting a state adds to its trace, or resets it when replacing
+        traces.visit(a, 1.0);
+        assert_eq!(traces.traces[&a], 1.25);
+        let mut replacing = EligibilityTraces::new(0.5, Traces::Replacing);
+        replacing.visit(a, 1.0);
+        replacing.visit(a, 1.0);
+        assert_eq!(replacing.traces[&a], 1.0);
+
+        // Without lambda only the latest state is traced
+        let mut td0 = EligibilityTraces::new(0.0, Traces::Accumulating);
+        td0.visit(a, 1.0);
+        td0.visit(b, 1.0);
+        assert_eq!(td0.traces.len(), 1);
+    }
+
+    fn params(algorithm: Algorithm, lambda: f64) -> TrainingParams {
+        TrainingParams { algorithm: algorithm, epsilon: 0.0, learning_rate: 0.5,
+                         discount_factor: 1.0, lambda: lambda, traces: Traces::Replacing }
+    }
+
+    #[test]
+    fn test_lambda_reaches_opening() {
+        // One game with full traces moves the value of the very first move, where TD(0) can't
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        let first_moves: Vec<GameState> = start.gen_actions()
+            .map(|action| start.afterstate(action))
+            .collect();
+        let mut td0: HashMap<GameState, f64> = HashMap::new();
+        training_loop(&mut td0, start, &params(Algorithm::Td, 0.0), 1, None);
+        let mut td1: HashMap<GameState, f64> = HashMap::new();
+        training_loop(&mut td1, start, &params(Algorithm::Td, 1.0), 1, None);
+        let moved = |values: &HashMap<GameState, f64>| first_moves.iter()
+            .any(|state| values.get(state).map_or(false, |&v| v != DEFAULT_STATE_VAL));
+        assert!(!moved(&td0));
+        assert!(moved(&td1));
+    }
+
+    #[test]
+    fn test_monte_carlo() {
+        let (a, b) = (GameState::new(1), GameState::new(2));
+        let mut every: HashMap<GameState, f64> = HashMap::new();
+        monte_carlo_update(&mut every, &[a, b, a], 1.0, &params(Algorithm::EveryVisit, 0.0));
+        assert_eq!(every[&a], 0.875);
+        assert_eq!(every[&b], 0.75);
+        let mut first: HashMap<GameState, f64> = HashMap::new();
+        monte_carlo_update(&mut first, &[a, b, a], 1.0, &params(Algorithm::FirstVisit, 0.0));
+        assert_eq!(first[&a], 0.75);
+
+        // Discounting shrinks the return the further a state is from the end
+        let mut discounted: HashMap<GameState, f64> = HashMap::new();
+        let params = TrainingParams { discount_factor: 0.5, .. params(Algorithm::EveryVisit, 0.0) };
+        monte_carlo_update(&mut discounted, &[a, b], 1.0, &params);
+        assert_eq!(discounted[&a], 0.5);
+        assert_eq!(discounted[&b], 0.75);
+    }
+
+    #[test]
+    fn test_algorithms_learn_the_result() {
+        // Every algorithm should get the final move of a one-game episode moving the right way
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        for &algorithm in [Algorithm::Td, Algorithm::QLearning, Algorithm::EveryVisit,
+                           Algorithm::FirstVisit].iter() {
+            let mut values: HashMap<GameState, f64> = HashMap::new();
+            training_loop(&mut values, start, &params(algorithm, 0.0), 1, None);
+            assert!(values.values().any(|&v| v != DEFAULT_STATE_VAL), "{:?}", algorithm);
+            assert!(values.values().all(|&v| v >= 0.0 && v <= 1.0), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_trains_network() {
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        let mut network = Network::new(4, 8);
+        training_loop(&mut network, start, &params(Algorithm::Td, 0.5), 5, None);
+        assert!(network != Network::new(4, 8));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = TrainingHistory::new();
+        assert_eq!(history.episodes(), 0);
+        for &(episodes, algorithm) in [(100, Algorithm::Td), (50, Algorithm::QLearning)].iter() {
+            history.sessions.push(Session { date: 0, episodes: episodes,
+                                            params: params(algorithm, 0.0) });
+        }
+        assert_eq!(history.episodes(), 150);
+    }
+
+    #[test]
+    fn test_resume() {
+        // Training picks up from whatever the values already hold
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        let mut values: HashMap<GameState, f64> = HashMap::new();
+        training_loop(&mut values, start, &params(Algorithm::Td, 0.0), 1, None);
+        let learned = values.clone();
+        training_loop(&mut values, start, &params(Algorithm::Td, 0.0), 1, None);
+        assert!(learned.keys().all(|state| values.contains_key(state)));
+        assert!(values != learned);
+    }
+
+    #[test]
+    fn test_counts_visits() {
+        // Every game plays into exactly one of the opening afterstates
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        let mut table = Table::with_capacity(0);
+        training_loop(&mut table, start, &params(Algorithm::Td, 0.0), 20, None);
+        let openings: u32 = start.gen_actions()
+            .map(|action| table.visits(&start.afterstate(action)))
+            .sum();
+        assert_eq!(openings, 20);
+        assert!(table.visits.keys().all(|state| table.values.contains_key(state)));
+    }
+}
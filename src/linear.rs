@@ -0,0 +1,171 @@
+use std::fmt::{self, Formatter, Display};
+use ::datafile::{self, Header};
+use ::mancala::{GameState, PlayerTurn};
+use ::value::ValueFunction;
+
+/// What each weight of a `Linear` value function multiplies. "Ours" is the side that just moved
+/// into the afterstate, "theirs" the side to move.
+pub const FEATURE_NAMES: [&'static str; FEATURES] = [
+    "bias",
+    "store difference",
+    "seeds on our side",
+    "seeds on their side",
+    "our captures",
+    "their captures",
+    "our extra turns",
+    "their extra turns",
+    "our mobility",
+    "their mobility",
+];
+
+pub const FEATURES: usize = 10;
+
+/// Sowings open to the player to move that take seeds from the opponent's houses, and that
+/// earn another turn
+fn tactics(state: &GameState) -> (usize, usize) {
+    if state.is_ended() {
+        return (0, 0);
+    }
+    let view = state.from_perspective(state.to_move());
+    let pits = view.pits();
+    let mut captures = 0;
+    let mut extra_turns = 0;
+    for sub in view.legal_subactions() {
+        let mut next = view;
+        next.apply_subaction(sub);
+        // Sowing only ever adds to the opponent's houses, so any that lost seeds were captured
+        if (pits + 1..2 * pits + 1).any(|i| next.houses()[i] < view.houses()[i]) {
+            captures += 1;
+        }
+        if next.to_move() == view.to_move() {
+            extra_turns += 1;
+        }
+    }
+    (captures, extra_turns)
+}
+
+/// `state` as seen from player one's side, with `side` to move
+fn with_to_move(state: &GameState, side: PlayerTurn) -> GameState {
+    let pits = state.pits();
+    let houses = state.houses();
+    GameState::from_sides(&houses[..pits], houses[pits], &houses[pits+1..2*pits+1],
+                          houses[2*pits+1], state.rules(), side).unwrap()
+}
+
+/// The features of an afterstate, which is seen from the perspective of the player who just
+/// moved. Seed counts are shares of all the seeds in the game and move counts are per house,
+/// so the same weights suit any board.
+pub fn features(state: &GameState) -> [f64; FEATURES] {
+    let pits = state.pits();
+    let houses = state.houses();
+    let total = houses.iter().map(|&h| h as f64).sum::<f64>().max(1.0);
+    let ours = houses[..pits].iter().map(|&h| h as f64).sum::<f64>();
+    let theirs = houses[pits+1..2*pits+1].iter().map(|&h| h as f64).sum::<f64>();
+    let (us, them) = (with_to_move(state, PlayerTurn::P1), with_to_move(state, PlayerTurn::P2));
+    let (our_captures, our_extra_turns) = tactics(&us);
+    let (their_captures, their_extra_turns) = tactics(&them);
+    let mobility = |state: &GameState| {
+        if state.is_ended() { 0.0 } else { state.gen_actions().count() as f64 / pits as f64 }
+    };
+    [1.0,
+     (houses[pits] as f64 - houses[2 * pits + 1] as f64) / total,
+     ours / total,
+     theirs / total,
+     our_captures as f64 / pits as f64,
+     their_captures as f64 / pits as f64,
+     our_extra_turns as f64 / pits as f64,
+     their_extra_turns as f64 / pits as f64,
+     mobility(&us),
+     mobility(&them)]
+}
+
+/// Values afterstates as a weighted sum of hand-picked `features`, trained by gradient descent
+/// on the squared error to each target. Untrained it values everything at the default of 0.5.
+#[derive(Debug, PartialEq, Clone, RustcDecodable, RustcEncodable)]
+pub struct Linear {
+    weights: Vec<f64>,
+}
+
+impl Linear {
+    pub fn new() -> Linear {
+        let mut weights = vec![0.0; FEATURES];
+        weights[0] = 0.5;
+        Linear { weights: weights }
+    }
+}
+
+impl ValueFunction for Linear {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        Some(features(state).iter().zip(&self.weights).map(|(x, w)| x * w).sum())
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        let features = features(state);
+        let error = target - self.value(state);
+        for (weight, feature) in self.weights.iter_mut().zip(features.iter()) {
+            *weight += learning_rate * error * feature;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        datafile::save_values(self, header, path)
+    }
+}
+
+impl Display for Linear {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (name, weight) in FEATURE_NAMES.iter().zip(&self.weights) {
+            try!(write!(f, "{:>20}: {:+.4}\n", name, weight));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, PlayerTurn, RuleSet};
+    use ::notation::Position;
+    use ::value::ValueFunction;
+
+    #[test]
+    fn test_features() {
+        // Player two has just moved into this, so player one is to move
+        let state = "1,0,3,0/2/0,1,2,0/5 1 kalah".parse::<Position>().unwrap().0
+            .from_perspective(PlayerTurn::P2);
+        let features = features(&state);
+        assert_eq!(features[0], 1.0);
+        assert_eq!(features[1], 3.0 / 14.0);
+        assert_eq!(features[2], 3.0 / 14.0);
+        assert_eq!(features[3], 4.0 / 14.0);
+        // Their single seed lands opposite our 2 and captures them, while our 2 land in our
+        // store for another turn
+        assert_eq!(features[4], 0.0);
+        assert_eq!(features[5], 0.25);
+        assert_eq!(features[6], 0.25);
+        assert_eq!(features[7], 0.0);
+        // We can play 1, 2 then 1, or 2 then 3 then 1; they can play either house
+        assert_eq!(features[8], 0.75);
+        assert_eq!(features[9], 0.5);
+    }
+
+    #[test]
+    fn test_learns() {
+        let mut linear = Linear::new();
+        let start = GameState::with_board(4, 3, RuleSet::kalah());
+        let action = start.gen_actions().next().unwrap();
+        let state = start.afterstate(action);
+        assert_eq!(linear.value(&state), 0.5);
+        for _ in 0..200 {
+            linear.update(&state, 1.0, 0.1);
+        }
+        assert!((linear.value(&state) - 1.0).abs() < 0.01);
+        // Other states generalize from the same weights
+        assert!(linear.value(&start) != 0.5);
+        assert_eq!(format!("{}", linear).lines().count(), FEATURES);
+    }
+}
@@ -1,9 +1,6 @@
 extern crate bincode;
 extern crate rustc_serialize;
 
-use bincode::SizeLimit;
-use bincode::rustc_serialize::{encode, decode};
-
 use std::fs::File;
 use std::io::{Read, Write};
 
@@ -19,8 +16,15 @@ const USAGE: &'static str = "
 Mancala AI using reinforcement learning.
 
 Usage:
-  mancala train [--num-runs=<num-runs>] [--learning-rate=<a>] [--discount-rate=<g>] [--epsilon=<epsilon>] [--train=<train>]
-  mancala play [--train=<train>]
+  mancala train [--num-runs=<num-runs>] [--learning-rate=<a>] [--discount-rate=<g>] [--epsilon=<epsilon>] [--algorithm=<algorithm>] [--lambda=<lambda>] [--traces=<traces>] [--model=<model>] [--hidden=<units>] [--initial=<initial>] [--initial-value=<v>] [--prior=<prior>] [--train=<train>] [--resume] [--checkpoint-every=<n>] [--keep-checkpoints=<k>] [--position=<position>] [options]
+  mancala play [--train=<train>] [--position=<position>] [--record=<record>] [--opponent=<opponent>] [--depth=<depth>] [--move-time=<ms>] [--evaluator=<evaluator>] [--simulations=<n>] [--rollouts=<rollouts>] [--endgame=<endgame>] [options]
+  mancala replay <record> [--train=<train>]
+  mancala migrate <datafile> [--model=<model>] [--position=<position>] [options]
+  mancala export [--train=<train>] [--format=<format>] [--output=<output>]
+  mancala import <input> [--format=<format>] [--train=<train>] [--model=<model>] [--position=<position>] [options]
+  mancala solve [--max-seeds=<n>] [--endgame=<endgame>] [options]
+  mancala solve --full [--train=<train>] [--position=<position>] [options]
+  mancala perft <depth> [--divide] [--position=<position>] [options]
   mancala (-h | --help)
   mancala --version
 
@@ -31,7 +35,76 @@ Options:
   --epsilon=<epsilon>    Epsilon for non-greedy actions [default: 0.02].
   --learning-rate=<a>    Learning rate [default: 0.05].
   --discount-rate=<g>    Discount rate [default: 1.0].
-  --train=<train>        Output/input training datafile.
+  --algorithm=<algorithm>  How to learn: td, qlearning, or Monte Carlo with
+                         everyvisit or firstvisit [default: td].
+  --lambda=<lambda>      Trace decay for TD(lambda), 0 for one-step TD
+                         [default: 0.0].
+  --traces=<traces>      Eligibility traces, accumulating or replacing
+                         [default: accumulating].
+  --model=<model>        What the training datafile holds: a table of values, a
+                         network that values any position, linear weights on
+                         board features, or a compact table, which is trained
+                         like a table but saved so that play can map it into
+                         memory and start at once. A table if not given, or
+                         when resuming whatever the datafile's header says it
+                         holds. Resuming a table as compact converts it.
+  --hidden=<units>       Hidden units in a new network, 32 if not given.
+  --initial=<initial>    What states never seen before are worth, and where
+                         learning them starts: constant (--initial-value),
+                         optimistic (a sure win, so every move gets tried),
+                         heuristic (from the store difference) or prior (what
+                         the --prior datafile says) [default: constant].
+  --initial-value=<v>    Value of unseen states for constant [default: 0.5].
+  --prior=<prior>        Datafile to take initial values from.
+  --train=<train>        Output/input training datafile. To play or replay with
+                         several at once give a comma separated list, and their
+                         values are averaged.
+  --resume               Carry on training the values already in the training
+                         datafile rather than starting afresh.
+  --checkpoint-every=<n>  Also save the values every n episodes, to the training
+                         datafile name followed by .checkpoint-<episodes>.
+                         Ctrl-C saves one last checkpoint and the training
+                         datafile before stopping.
+  --keep-checkpoints=<k>  How many of the latest checkpoints to keep, at least
+                         one [default: 3].
+  --format=<format>      How export writes and import reads a table, a row per
+                         state with its value and visits in training: csv (with
+                         a header line) or jsonl [default: csv].
+  --output=<output>      Where export writes, standard output if not given.
+  --record=<record>      Where to write the game record, game-<timestamp>.txt if
+                         not given.
+  --opponent=<opponent>  Who to play against: table (the learned values),
+                         search or mcts [default: table].
+  --depth=<depth>        How many moves ahead the search opponent looks
+                         [default: 6].
+  --move-time=<ms>       Let the search or mcts opponent think for this many
+                         milliseconds a move instead of a fixed depth or number
+                         of simulations.
+  --evaluator=<evaluator>  How the search opponent scores positions: stores
+                         (store difference) or table [default: stores].
+  --simulations=<n>      Games the mcts opponent simulates a move [default: 1000].
+  --rollouts=<rollouts>  How the mcts opponent plays out simulations: random or
+                         table (guided by the learned values) [default: random].
+  --endgame=<endgame>    Endgame database written by solve, which the table and
+                         search opponents use to play endgames perfectly.
+                         solve writes to endgame.dat if not given.
+  --full                 Solve the whole game from the starting position instead,
+                         comparing the training datafile against it if there is
+                         one. Only practical on small boards.
+  --divide               Count the positions at the full depth after each first
+                         action separately, to narrow down where two move
+                         generators disagree.
+  --max-seeds=<n>        Solve every endgame with up to this many seeds left
+                         in the houses [default: 10].
+  --position=<position>  Start from a position in notation, e.g.
+                         '4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah'. Overrides the
+                         board and rule options.
+  --variant=<variant>    Game to play, kalah or oware [default: kalah].
+  --pits=<pits>          Houses on each side of the board, 4 to 8 [default: 6].
+  --seeds=<seeds>        Seeds initially in each house [default: 1].
+  --no-empty-capture     Only capture when the opposite house has seeds.
+  --sweep-to-mover       Remaining seeds go to the player who ended the game.
+  --no-extra-turns       Landing in your own store does not grant another turn.
 ";
 
 #[derive(Debug, RustcDecodable)]
@@ -40,23 +113,137 @@ struct Args {
     flag_epsilon: f64,
     flag_learning_rate: f64,
     flag_discount_rate: f64,
+    flag_algorithm: learning::Algorithm,
+    flag_lambda: f64,
+    flag_traces: learning::Traces,
+    flag_model: Option<value::Model>,
+    flag_hidden: Option<usize>,
+    flag_initial: Initialization,
+    flag_initial_value: f64,
+    flag_prior: Option<String>,
     flag_train: Option<String>,
+    flag_resume: bool,
+    flag_checkpoint_every: Option<usize>,
+    flag_keep_checkpoints: usize,
+    flag_position: Option<String>,
+    flag_record: Option<String>,
+    flag_endgame: Option<String>,
+    flag_max_seeds: usize,
+    flag_full: bool,
+    flag_divide: bool,
+    arg_record: String,
+    arg_datafile: String,
+    arg_input: String,
+    flag_format: export::Format,
+    flag_output: Option<String>,
+    arg_depth: usize,
+    flag_opponent: Opponent,
+    flag_depth: usize,
+    flag_move_time: Option<u64>,
+    flag_evaluator: Evaluation,
+    flag_simulations: usize,
+    flag_rollouts: Rollouts,
+    flag_variant: mancala::Variant,
+    flag_pits: usize,
+    flag_seeds: u8,
+    flag_no_empty_capture: bool,
+    flag_sweep_to_mover: bool,
+    flag_no_extra_turns: bool,
     cmd_train: bool,
     cmd_play: bool,
+    cmd_replay: bool,
+    cmd_migrate: bool,
+    cmd_export: bool,
+    cmd_import: bool,
+    cmd_solve: bool,
+    cmd_perft: bool,
+}
+
+#[derive(Debug, RustcDecodable)]
+enum Initialization {
+    Constant,
+    Optimistic,
+    Heuristic,
+    Prior,
+}
+
+#[derive(Debug, RustcDecodable)]
+enum Opponent {
+    Table,
+    Search,
+    Mcts,
+}
+
+#[derive(Debug, RustcDecodable)]
+enum Evaluation {
+    Stores,
+    Table,
+}
+
+#[derive(Debug, RustcDecodable)]
+enum Rollouts {
+    Random,
+    Table,
+}
+
+/// Report `error` and give up
+fn fail<T>(error: String) -> T {
+    println!("{}", error);
+    std::process::exit(1)
+}
+
+/// The `model` value function saved at `path` to carry on training if resuming, otherwise
+/// `fresh`
+fn resume_or<T: rustc_serialize::Decodable, F: FnOnce() -> T>(resume: bool, path: &str,
+                                                               model: value::Model,
+                                                               fresh: F) -> T {
+    if resume {
+        datafile::load_values(path, model).unwrap_or_else(fail).1
+    } else {
+        fresh()
+    }
+}
+
+/// Load the value functions in the comma separated `paths` to play games like `state` with,
+/// averaging them if there are several
+fn load_value_function(paths: &str, state: &mancala::GameState) -> Box<value::ValueFunction> {
+    let mut members: Vec<Box<value::ValueFunction>> = paths.split(',')
+        .map(|path| value::load(path, state).unwrap_or_else(fail))
+        .collect();
+    let values: Box<value::ValueFunction> = if members.len() == 1 {
+        members.pop().unwrap()
+    } else {
+        Box::new(value::Ensemble::new(members))
+    };
+    Box::new(value::ReadOnly::new(values))
 }
 
 
 #[macro_use]
 extern crate log;
 extern crate env_logger;
+extern crate libc;
 extern crate rand;
 
-use std::collections::HashMap;
 
 mod packed_actions;
 mod mancala;
+mod notation;
 mod player;
 mod learning;
+mod record;
+mod search;
+mod mcts;
+mod transposition;
+mod endgame;
+mod solver;
+mod perft;
+mod value;
+mod network;
+mod linear;
+mod datafile;
+mod compact;
+mod export;
 
 fn main() {
     env_logger::init().unwrap();
@@ -65,59 +252,340 @@ fn main() {
                             .and_then(|d| d.decode())
                             .unwrap_or_else(|e| e.exit());
 
-    let starting_state = mancala::GameState::new(1);
-    println!("{}", starting_state);
-    if args.cmd_train {
-        let mut value_fun: HashMap<mancala::GameState, f64> = HashMap::with_capacity(1_000);
-        learning::sarsa_loop(&mut value_fun,
-                   starting_state,
-                   args.flag_epsilon,
-                   args.flag_learning_rate,
-                   args.flag_discount_rate,
-                   args.flag_num_runs);
+    if args.cmd_replay {
+        let mut text = String::new();
+        File::open(&args.arg_record).and_then(|mut f| f.read_to_string(&mut text))
+            .unwrap_or_else(|e| fail(format!("Can't read game record {}: {}", args.arg_record, e)));
+        let game: record::GameRecord = text.parse().unwrap_or_else(|e| {
+            println!("Invalid game record {}: {}", args.arg_record, e);
+            std::process::exit(1);
+        });
+        let value_fun = load_value_function(&args.flag_train.unwrap_or("train.dat".to_string()),
+                                            &game.start);
+        record::replay(&game, &*value_fun);
+        return;
+    }
 
-        println!("Number of entries in value function: {}", value_fun.len());
+    if args.cmd_export {
+        let train = args.flag_train.unwrap_or("train.dat".to_string());
+        let (header, table) = value::read_table(&train).unwrap_or_else(fail);
+        let format = args.flag_format;
+        let result = match args.flag_output {
+            Some(ref path) => File::create(path).and_then(|mut f| {
+                export::export(&table, header.pits, format, &mut f)
+            }),
+            None => {
+                let stdout = std::io::stdout();
+                let mut out = stdout.lock();
+                export::export(&table, header.pits, format, &mut out)
+            }
+        };
+        result.unwrap_or_else(|e| fail(format!("Failed to export {}: {}", train, e)));
+        return;
+    }
 
-        let mut vals = value_fun.iter().collect::<Vec<_>>();
-        vals.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-        println!("Here's a few of the top values and states:");
-        for pair in vals.iter().take(2) {
-            println!("\n#########\n{}:\n", pair.1);
-            println!("{}", pair.0);
+    let position = match args.flag_position {
+        Some(ref notation) => notation.parse::<notation::Position>().unwrap_or_else(|e| {
+            println!("Invalid position {:?}: {}", notation, e);
+            std::process::exit(1);
+        }),
+        None => {
+            let mut rules = mancala::RuleSet::for_variant(args.flag_variant);
+            if args.flag_no_empty_capture {
+                rules.capture_empty_opposite = false;
+            }
+            if args.flag_sweep_to_mover {
+                rules.sweep_to_owner = false;
+            }
+            if args.flag_no_extra_turns {
+                rules.extra_turns = false;
+            }
+            if args.flag_pits < mancala::MIN_PITS || args.flag_pits > mancala::MAX_PITS {
+                println!("Boards must have between {} and {} pits per side",
+                         mancala::MIN_PITS, mancala::MAX_PITS);
+                std::process::exit(1);
+            }
+            if 2 * args.flag_pits * args.flag_seeds as usize > mancala::MAX_TOTAL_SEEDS {
+                println!("At most {} seeds fit on the board", mancala::MAX_TOTAL_SEEDS);
+                std::process::exit(1);
+            }
+            notation::Position(mancala::GameState::with_board(args.flag_pits, args.flag_seeds, rules))
+        }
+    };
+    if args.cmd_migrate {
+        let model = args.flag_model.unwrap_or(value::Model::Table);
+        let header = value::migrate(&args.arg_datafile, model, &position.0)
+            .unwrap_or_else(fail);
+        println!("Added a header to {} for {:?} values on {} pit {}, trained for {} episodes",
+                 args.arg_datafile, header.model, header.pits, header.rules,
+                 header.history.episodes());
+        return;
+    }
+    if args.cmd_import {
+        let model = args.flag_model.unwrap_or(value::Model::Table);
+        if model != value::Model::Table && model != value::Model::Compact {
+            fail::<()>("Only tables can be imported".to_string());
+        }
+        let file = File::open(&args.arg_input)
+            .unwrap_or_else(|e| fail(format!("can't open {}: {}", args.arg_input, e)));
+        let table = export::import(&mut std::io::BufReader::new(file), args.flag_format,
+                                   position.0.rules(), position.0.pits())
+            .unwrap_or_else(|e| fail(format!("Invalid {}: {}", args.arg_input, e)));
+        let train = args.flag_train.unwrap_or("train.dat".to_string());
+        let header = datafile::Header::new(model, &position.0,
+                                           learning::TrainingHistory::new());
+        value::ValueFunction::save(&table, &train, &header).unwrap_or_else(fail);
+        println!("Imported {} states from {} into {}", table.values.len(), args.arg_input, train);
+        return;
+    }
+    if args.cmd_perft && args.flag_divide {
+        println!("Perft {} from position {} by first action", args.arg_depth, position);
+        let divided = perft::divide(&position.0, args.arg_depth);
+        for &(action, count) in &divided {
+            println!("{:>16} {:>16}", action.notation(), count);
         }
-        vals.sort_by(|a, b| a.1.partial_cmp(b.1).unwrap());
-        println!("Here's a few of the bottom values and states:");
-        for pair in vals.iter().take(2) {
-            println!("\n#########\nValue: {}:\n{}", pair.1, pair.0);
+        println!("{:>16} {:>16}", "total", divided.iter().map(|&(_, count)| count).sum::<u64>());
+        return;
+    }
+    if args.cmd_perft {
+        println!("Perft from position {}", position);
+        println!("{:>5} {:>16} {:>16}", "depth", "turns", "sub-actions");
+        for depth in 1..args.arg_depth + 1 {
+            println!("{:>5} {:>16} {:>16}", depth, perft::perft(&position.0, depth),
+                     perft::perft_subactions(&position.0, depth));
+        }
+        return;
+    }
+    if args.cmd_solve && args.flag_full {
+        let start = position.0;
+        if start.rules().variant != mancala::Variant::Kalah {
+            println!("Only Kalah games can be solved");
+            std::process::exit(1);
         }
+        println!("Solving from position {}", position);
+        let mut game_solver = solver::GameSolver::new();
+        let margin = game_solver.margin(&start);
+        println!("Solved {} positions", game_solver.len());
+        println!("Player {} {} by {} under perfect play",
+                 if start.to_move() == mancala::PlayerTurn::P1 { 1 } else { 2 },
+                 if margin > 0 { "wins" } else if margin < 0 { "loses" } else { "ties" },
+                 margin.abs());
+        let variation = game_solver.principal_variation(&start);
+        println!("Principal variation: {}",
+                 variation.iter().map(|action| action.notation()).collect::<Vec<_>>().join(" "));
 
-        let encoded: Vec<u8> = encode(&value_fun, SizeLimit::Infinite).unwrap();
-        let mut f: File = File::create(args.flag_train.unwrap_or("train.dat".to_string())).unwrap();
-        f.write_all(&encoded).unwrap();
-        drop(f);
-    } else if args.cmd_play {
-        let mut f: File = File::open(args.flag_train.unwrap_or("train.dat".to_string())).unwrap();
-        let mut encoded = Vec::new();
-        f.read_to_end(&mut encoded).unwrap();
-        let mut value_fun: HashMap<mancala::GameState, f64> = decode(&encoded).unwrap();
-        println!("Number of values in hash: {}", value_fun.len());
-        println!();
-        println!("Here are the first possible actions and their values: ");
-        for action in starting_state.gen_actions() {
-            let mut state = starting_state;
-            state.evaluate_action(action);
-            println!("\n----------------\n{}:\n{}\nqval: {:?}\n", action, state, value_fun.get(&state));
+        let train = args.flag_train.unwrap_or("train.dat".to_string());
+        if std::path::Path::new(&train).exists() {
+            let value_fun = value::load_table(&train, &start).unwrap_or_else(fail);
+            let comparison = solver::compare(&value_fun.values, &mut game_solver, &start);
+            println!("Compared {} learned states from {}", comparison.states, train);
+            println!("Mean squared error against perfect play: {:.4}", comparison.mean_squared_error);
+            println!("Greedy moves keeping the best result: {:.1}%", 100.0 * comparison.optimal_moves);
+        }
+        return;
+    }
+    if args.cmd_solve {
+        let rules = position.0.rules();
+        if rules.variant != mancala::Variant::Kalah {
+            println!("Only Kalah endgames can be solved");
+            std::process::exit(1);
         }
-        println!("\n----------------\n");
+        if args.flag_max_seeds > std::i8::MAX as usize {
+            println!("Can't solve endgames with more than {} seeds", std::i8::MAX);
+            std::process::exit(1);
+        }
+        println!("Solving {} endgames with up to {} seeds on {} pits",
+                 rules, args.flag_max_seeds, position.0.pits());
+        let db = endgame::EndgameDb::solve(position.0.pits(), rules, args.flag_max_seeds);
+        println!("Number of positions in endgame database: {}", db.len());
+        datafile::save(&db, &args.flag_endgame.unwrap_or("endgame.dat".to_string()))
+            .unwrap_or_else(fail);
+        return;
+    }
 
-        use player::{HumanPlayer, AIPlayer, Player};
-        let p1 = Box::new(HumanPlayer::new(starting_state));
-        let p2 = Box::new({
-            let mut opp_starting_state = starting_state.clone();
-            opp_starting_state.swap_board();
-            AIPlayer::new(opp_starting_state)
+    println!("Starting from position {}", position);
+    let starting_state = position.0;
+    println!("{}", starting_state);
+    if args.cmd_train {
+        let params = learning::TrainingParams {
+            algorithm: args.flag_algorithm,
+            epsilon: args.flag_epsilon,
+            learning_rate: args.flag_learning_rate,
+            discount_factor: args.flag_discount_rate,
+            lambda: args.flag_lambda,
+            traces: args.flag_traces,
+        };
+        let initial = match args.flag_initial {
+            Initialization::Constant => value::Initial::Constant(args.flag_initial_value),
+            Initialization::Optimistic => value::Initial::Constant(value::OPTIMISTIC_STATE_VAL),
+            Initialization::Heuristic => value::Initial::StoreDifference,
+            Initialization::Prior => {
+                let prior = args.flag_prior.as_ref()
+                    .unwrap_or_else(|| fail("--initial=prior needs a --prior datafile".to_string()));
+                value::Initial::Prior(value::load(prior, &starting_state).unwrap_or_else(fail))
+            }
+        };
+        let train = args.flag_train.unwrap_or("train.dat".to_string());
+        let resume = args.flag_resume;
+        let mut resumed_model = None;
+        let mut history = if resume {
+            let header = datafile::load_header(&train).unwrap_or_else(fail);
+            header.check(&train, &starting_state).unwrap_or_else(fail);
+            println!("Resuming from {} after {} episodes in {} sessions", train,
+                     header.history.episodes(), header.history.sessions.len());
+            resumed_model = Some(header.model);
+            header.history
+        } else {
+            learning::TrainingHistory::new()
+        };
+        let model = args.flag_model.or(resumed_model).unwrap_or(value::Model::Table);
+        let date = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs()).unwrap_or(0);
+        history.sessions.push(learning::Session { date: date, episodes: 0, params: params });
+        let mut header = datafile::Header::new(model, &starting_state, history);
+        let keep = args.flag_keep_checkpoints;
+        if keep == 0 && args.flag_checkpoint_every.is_some() {
+            fail::<()>("--keep-checkpoints must be at least 1 to checkpoint at all".to_string());
+        }
+        let mut checkpoints = args.flag_checkpoint_every.map(|every| {
+            datafile::Checkpoints::new(&train, every, keep, header.clone())
         });
+        learning::stop_on_interrupt();
+        let mut episodes = args.flag_num_runs;
+        let mut train_values = |values: &mut value::ValueFunction| {
+            let mut values = value::Initialized::new(values, &initial);
+            episodes = learning::training_loop(&mut values, starting_state, &params, episodes,
+                                               checkpoints.as_mut());
+            let (lookups, unseen) = values.lookups();
+            println!("{} of {} value lookups were of unseen states", unseen, lookups);
+        };
+        let value_fun: Box<value::ValueFunction> = match model {
+            // A compact datafile is trained where it's mapped, so only the states reached are
+            // held in memory, unless --model asks to convert it
+            value::Model::Compact if resumed_model == Some(value::Model::Compact) => {
+                let (_, mut compact) = compact::CompactTable::open(&train).unwrap_or_else(fail);
+                train_values(&mut compact);
+                Box::new(compact)
+            }
+            value::Model::Table | value::Model::Compact => {
+                let mut value_fun = if resume {
+                    value::load_table(&train, &starting_state).unwrap_or_else(fail)
+                } else {
+                    value::Table::with_capacity(1_000)
+                };
+                train_values(&mut value_fun);
+
+                let mut vals = value_fun.values.iter().collect::<Vec<_>>();
+                vals.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+                println!("Here's a few of the top values and states:");
+                for pair in vals.iter().take(2) {
+                    println!("\n#########\n{}:\n", pair.1);
+                    println!("{}", pair.0);
+                }
+                vals.sort_by(|a, b| a.1.partial_cmp(b.1).unwrap());
+                println!("Here's a few of the bottom values and states:");
+                for pair in vals.iter().take(2) {
+                    println!("\n#########\nValue: {}:\n{}", pair.1, pair.0);
+                }
+                Box::new(value_fun)
+            }
+            value::Model::Network => {
+                let hidden = args.flag_hidden.unwrap_or(network::DEFAULT_HIDDEN_UNITS);
+                let mut network = resume_or(resume, &train, model, || {
+                    network::Network::new(starting_state.pits(), hidden)
+                });
+                train_values(&mut network);
+                Box::new(network)
+            }
+            value::Model::Linear => {
+                let mut linear = resume_or(resume, &train, model, linear::Linear::new);
+                train_values(&mut linear);
+                println!("Learned weights:\n{}", linear);
+                Box::new(linear)
+            }
+        };
+        println!("Number of entries in value function: {}", value_fun.len());
+        header.history.sessions.last_mut().unwrap().episodes = episodes;
+        value_fun.save(&train, &header).unwrap_or_else(fail);
+        println!("Trained for {} episodes in {} sessions", header.history.episodes(),
+                 header.history.sessions.len());
+    } else if args.cmd_play {
+        let train = args.flag_train.unwrap_or("train.dat".to_string());
+        // Only opponents that consult learned values need a training datafile
+        let needs_values = match args.flag_opponent {
+            Opponent::Table => true,
+            Opponent::Search => match args.flag_evaluator {
+                Evaluation::Table => true,
+                Evaluation::Stores => false,
+            },
+            Opponent::Mcts => match args.flag_rollouts {
+                Rollouts::Table => true,
+                Rollouts::Random => false,
+            },
+        };
+        let mut value_fun: Box<value::ValueFunction> = if needs_values {
+            let value_fun = load_value_function(&train, &starting_state);
+            println!("Number of entries in value function: {}", value_fun.len());
+            println!("Here are the first possible actions and their values: ");
+            for action in starting_state.gen_actions() {
+                let state = starting_state.afterstate(action);
+                println!("\n----------------\n{}:\n{}\nqval: {:?}\n", action, state, value_fun.get(&state));
+            }
+            println!("\n----------------\n");
+            value_fun
+        } else {
+            Box::new(std::collections::HashMap::<mancala::GameState, f64>::new())
+        };
+
+        // The human always plays the player one side, but may not be the one to move first
+        use player::{HumanPlayer, AIPlayer, Player};
+        let human = Box::new(HumanPlayer::new());
+        let endgame = args.flag_endgame.as_ref().map(|path| datafile::load::<endgame::EndgameDb>(path).unwrap_or_else(fail));
+        let computer: Box<Player> = match args.flag_opponent {
+            Opponent::Table => {
+                let player = AIPlayer::new(mancala::PlayerTurn::P2, &starting_state);
+                Box::new(match endgame {
+                    Some(endgame) => player.with_endgame(endgame),
+                    None => player,
+                })
+            }
+            Opponent::Search => {
+                let evaluator: Box<search::Evaluator> = match args.flag_evaluator {
+                    Evaluation::Stores => Box::new(search::StoreDifference),
+                    Evaluation::Table => Box::new(search::TableEvaluator::new(
+                        load_value_function(&train, &starting_state))),
+                };
+                let player = match args.flag_move_time {
+                    Some(ms) => search::SearchPlayer::timed(std::time::Duration::from_millis(ms),
+                                                            evaluator),
+                    None => search::SearchPlayer::new(args.flag_depth, evaluator),
+                };
+                Box::new(match endgame {
+                    Some(endgame) => player.with_endgame(endgame),
+                    None => player,
+                })
+            }
+            Opponent::Mcts => {
+                let budget = match args.flag_move_time {
+                    Some(ms) => mcts::Budget::Time(std::time::Duration::from_millis(ms)),
+                    None => mcts::Budget::Simulations(args.flag_simulations),
+                };
+                let rollout = match args.flag_rollouts {
+                    Rollouts::Random => mcts::Rollout::Random,
+                    Rollouts::Table => mcts::Rollout::Guided(args.flag_epsilon),
+                };
+                Box::new(mcts::MctsPlayer::new(budget, rollout))
+            }
+        };
 
-        player::play_loop(p1 as Box<Player>, p2 as Box<Player>, &mut value_fun, starting_state);
+        let game = player::play_loop(human as Box<Player>, computer,
+                                     &mut *value_fun, starting_state);
+        let record_path = args.flag_record.unwrap_or(format!("game-{}.txt", game.date));
+        // Show the record rather than lose it if it can't be written
+        File::create(&record_path).and_then(|mut f| write!(f, "{}", game))
+            .unwrap_or_else(|e| {
+                fail(format!("Can't write game record to {}: {}\n{}", record_path, e, game))
+            });
+        println!("Game record written to {}", record_path);
     }
 }
@@ -1,14 +1,23 @@
 use std::fmt::{self, Formatter, Display};
 use packed_actions::{Action, SubAction, ActionQueue};
-use std::collections::HashMap;
+use value::ValueFunction;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, RustcDecodable, RustcEncodable)]
 pub enum PlayerTurn {
     P1,
     P2,
 }
 
-#[derive(Debug, PartialEq)]
+impl PlayerTurn {
+    pub fn other(self) -> PlayerTurn {
+        match self {
+            PlayerTurn::P1 => PlayerTurn::P2,
+            PlayerTurn::P2 => PlayerTurn::P1,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Outcome {
     P1Win,
     P2Win,
@@ -16,24 +25,216 @@ pub enum Outcome {
 }
 use self::Outcome::*;
 
+impl Outcome {
+    /// The same outcome from the other player's point of view
+    pub fn reversed(self) -> Outcome {
+        match self {
+            P1Win => P2Win,
+            P2Win => P1Win,
+            Tie => Tie,
+        }
+    }
+}
+
+/// Which family of sowing game is being played.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub enum Variant {
+    /// Sowing passes through your own store and captures happen on your own side.
+    Kalah,
+    /// Oware (Abapa): stores only hold captures, captures of 2s and 3s happen on the
+    /// opponent's side and you must always leave the opponent something to play.
+    Oware,
+}
+
+/// The knobs that distinguish the variants we play against.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub struct RuleSet {
+    pub variant: Variant,
+    /// Landing in an empty house of your own captures even when the opposite house is empty
+    /// (capturing just the one seed). When false such a landing captures nothing.
+    pub capture_empty_opposite: bool,
+    /// At the end of the game the seeds left on a side go to that side's owner. When false they
+    /// all go to the player who made the final move.
+    pub sweep_to_owner: bool,
+    /// Landing the last seed in your own store grants another subaction.
+    pub extra_turns: bool,
+}
+
+impl RuleSet {
+    /// Standard Kalah as played in most tournaments.
+    pub fn kalah() -> RuleSet {
+        RuleSet {
+            variant: Variant::Kalah,
+            capture_empty_opposite: true,
+            sweep_to_owner: true,
+            extra_turns: true,
+        }
+    }
+
+    /// Oware with the Abapa rules. When the opponent cannot be fed, the mover keeps the seeds
+    /// left on their own side.
+    pub fn oware() -> RuleSet {
+        RuleSet {
+            variant: Variant::Oware,
+            capture_empty_opposite: false,
+            sweep_to_owner: true,
+            extra_turns: false,
+        }
+    }
+
+    /// The rules a variant is played with unless told otherwise
+    pub fn for_variant(variant: Variant) -> RuleSet {
+        match variant {
+            Variant::Kalah => RuleSet::kalah(),
+            Variant::Oware => RuleSet::oware(),
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> RuleSet {
+        RuleSet::kalah()
+    }
+}
+
+/// Fewest houses per side a board can be built with
+pub const MIN_PITS: usize = 4;
+/// Most houses per side a board can be built with
+pub const MAX_PITS: usize = 8;
+/// Most houses a board can have, stores included
+pub const MAX_HOUSES: usize = 2 * MAX_PITS + 2;
+/// Most seeds a board can hold in total, so that no single house can ever overflow
+pub const MAX_TOTAL_SEEDS: usize = ::std::u8::MAX as usize;
+
+/// A board of `pits` houses per side followed by a store, player one's side first, along with
+/// whose turn it is. Houses past the two sides are unused and always zero so they don't disturb
+/// hashing.
+///
+/// Moves are made for whoever is to move with `apply`. Players that want to think about the board
+/// as if they were player one (as the value function does) use `from_perspective`.
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, RustcDecodable, RustcEncodable)]
 pub struct GameState {
-    houses: [u8; 14],
+    houses: [u8; MAX_HOUSES],
+    pits: u8,
+    rules: RuleSet,
+    to_move: PlayerTurn,
 }
 
 impl GameState {
     /// Create a new board initialized with each house having `starting_seeds` number of seeds.
     pub fn new(starting_seeds: u8) -> GameState {
-        let mut state = GameState{ houses: [starting_seeds; 14] };
-        state.houses[6] = 0;
-        state.houses[13] = 0;
+        GameState::with_rules(starting_seeds, RuleSet::kalah())
+    }
+
+    /// Create a new board like `new` but played under the given `rules`.
+    pub fn with_rules(starting_seeds: u8, rules: RuleSet) -> GameState {
+        GameState::with_board(6, starting_seeds, rules)
+    }
+
+    /// Create a new board with `pits` houses on each side, each holding `starting_seeds` seeds.
+    pub fn with_board(pits: usize, starting_seeds: u8, rules: RuleSet) -> GameState {
+        assert!(pits >= MIN_PITS && pits <= MAX_PITS, "unsupported board size {}", pits);
+        assert!(2 * pits * starting_seeds as usize <= MAX_TOTAL_SEEDS,
+                "{} seeds per house is too many for {} pits", starting_seeds, pits);
+        let mut state = GameState{ houses: [0; MAX_HOUSES], pits: pits as u8, rules: rules,
+                                   to_move: PlayerTurn::P1 };
+        for i in 0..pits {
+            state.houses[i] = starting_seeds;
+            state.houses[pits + 1 + i] = starting_seeds;
+        }
         state
     }
+
+    /// Create a board from explicit house and store counts for each side, checking that the
+    /// sides match and the board is a size we support.
+    pub fn from_sides(p1_houses: &[u8], p1_store: u8, p2_houses: &[u8], p2_store: u8,
+                      rules: RuleSet, to_move: PlayerTurn) -> Result<GameState, String> {
+        let pits = p1_houses.len();
+        if p2_houses.len() != pits {
+            return Err(format!("sides have different numbers of houses ({} and {})",
+                               pits, p2_houses.len()));
+        }
+        if pits < MIN_PITS || pits > MAX_PITS {
+            return Err(format!("boards must have between {} and {} houses per side, not {}",
+                               MIN_PITS, MAX_PITS, pits));
+        }
+        let total = p1_houses.iter().chain(p2_houses).map(|&h| h as usize).sum::<usize>()
+            + p1_store as usize + p2_store as usize;
+        if total > MAX_TOTAL_SEEDS {
+            return Err(format!("{} seeds is more than the {} a board can hold",
+                               total, MAX_TOTAL_SEEDS));
+        }
+        let mut state = GameState{ houses: [0; MAX_HOUSES], pits: pits as u8, rules: rules,
+                                   to_move: to_move };
+        state.houses[..pits].copy_from_slice(p1_houses);
+        state.houses[pits] = p1_store;
+        state.houses[pits+1..2*pits+1].copy_from_slice(p2_houses);
+        state.houses[2*pits+1] = p2_store;
+        Ok(state)
+    }
+
+    /// Number of houses on each side of the board
+    pub fn pits(&self) -> usize {
+        self.pits as usize
+    }
+
+    /// The houses in use, player one's side and store followed by player two's
+    pub fn houses(&self) -> &[u8] {
+        &self.houses[..2 * self.pits() + 2]
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn to_move(&self) -> PlayerTurn {
+        self.to_move
+    }
+
+    /// The same position seen by `player`, i.e. rotated so that `player` sits on the player one
+    /// side
+    pub fn from_perspective(&self, player: PlayerTurn) -> GameState {
+        let mut view = *self;
+        if player == PlayerTurn::P2 {
+            view.swap_board();
+            view.to_move = view.to_move.other();
+        }
+        view
+    }
+
+    /// Index of player one's store
+    fn store(&self) -> usize {
+        self.pits()
+    }
+
+    /// Index of player two's store (always the last house in use)
+    fn opponent_store(&self) -> usize {
+        2 * self.pits() + 1
+    }
+
+    /// Seeds still in play on player one's and player two's sides
+    fn side_totals(&self) -> (u32, u32) {
+        let pits = self.pits();
+        (self.houses[..pits].iter().map(|&h| h as u32).sum(),
+         self.houses[pits+1..2*pits+1].iter().map(|&h| h as u32).sum())
+    }
+
+    /// Drop `seeds` into `house`. Boards are built so this can't overflow, but we'd rather panic
+    /// than silently wrap if it somehow does.
+    fn add_seeds(&mut self, house: usize, seeds: u8) {
+        self.houses[house] = self.houses[house].checked_add(seeds)
+            .expect("seed count overflowed a house");
+    }
     
     /// Is the game completely over where one player has emptied their side of the board?
+    ///
+    /// In Oware the game is instead over once either player has captured a majority of the seeds
+    /// or the player to move has no legal move.
     pub fn is_ended(&self) -> bool {
-        let p1_tot: u8 = self.houses[..6].iter().sum();
-        let p2_tot: u8 = self.houses[7..13].iter().sum();
+        if self.rules.variant == Variant::Oware {
+            return self.is_oware_ended();
+        }
+        let (p1_tot, p2_tot) = self.side_totals();
         if p1_tot == 0 || p2_tot == 0 {
             return true;
         }
@@ -41,43 +242,76 @@ impl GameState {
         return false;
     }
 
-    /// Is the game a winning final state for current player?
+    fn is_oware_ended(&self) -> bool {
+        let total: u32 = self.houses.iter().map(|&h| h as u32).sum();
+        let (store, opponent_store) = (self.houses[self.store()] as u32,
+                                       self.houses[self.opponent_store()] as u32);
+        if store * 2 > total || opponent_store * 2 > total {
+            return true;
+        }
+        self.from_perspective(self.to_move).find_next_subaction(0).is_none()
+    }
+
+    /// Has the game been won, from player one's point of view?
     /// None here means the game is not done.
     pub fn is_won(&self) -> Option<Outcome> {
-        let p1_tot: u8 = self.houses[..6].iter().sum();
-        let p2_tot: u8 = self.houses[7..13].iter().sum();
-        if p1_tot != 0 && p2_tot != 0 {
+        if !self.is_ended() {
             return None;
         }
-        let p1_tot = p1_tot + self.houses[6];
-        let p2_tot = p2_tot + self.houses[13];
-        if p1_tot > p2_tot {
-            Some(P1Win)
-        } else if p2_tot > p1_tot {
-            Some(P2Win)
+        Some(self.final_outcome())
+    }
+
+    /// The outcome from player one's point of view if the game were stopped here and the board
+    /// swept, as happens in Oware when a position repeats. The player who is not to move is taken
+    /// to have made the last move, which matters when the rules sweep to the mover.
+    pub fn final_outcome(&self) -> Outcome {
+        let mover = self.to_move.other();
+        let mut finalized = self.from_perspective(mover);
+        finalized.finalize_game();
+        let mover_tot: u32 = finalized.houses[..self.store()+1].iter().map(|&h| h as u32).sum();
+        let opponent_tot = finalized.houses[self.opponent_store()] as u32;
+        let outcome = if mover_tot > opponent_tot {
+            P1Win
+        } else if opponent_tot > mover_tot {
+            P2Win
         } else {
-            Some(Tie)
-        }
+            Tie
+        };
+        if mover == PlayerTurn::P1 { outcome } else { outcome.reversed() }
     }
 
-    /// Move other players seeds to their house after a game ends
+    /// Move other players seeds to their house (or ours, if the rules sweep to the mover) after
+    /// a game ends
     fn finalize_game(&mut self) {
-        for i in 7..13 {
-            self.houses[13] += self.houses[i];
+        let store = if self.rules.sweep_to_owner { self.opponent_store() } else { self.store() };
+        for i in self.store()+1..self.opponent_store() {
+            let seeds = self.houses[i];
+            self.add_seeds(store, seeds);
             self.houses[i] = 0;
         }
     }
 
-    /// Return a new game state when playing out a sequence of actions (a string of capturing
-    /// moves)
-    fn evaluate_to_new_state(&self, action_list: Action) -> GameState {
-        let mut new_state = self.clone();
-        new_state.evaluate_action(action_list);
-        new_state
+    /// Play `action` for the player to move and pass the turn to their opponent
+    pub fn apply(&mut self, action: Action) {
+        let mover = self.to_move;
+        let mut view = self.from_perspective(mover);
+        view.evaluate_action(action);
+        view.to_move = PlayerTurn::P2;
+        *self = view.from_perspective(mover);
     }
 
-    /// Mutate the current game state when playing out a full action sequence
-    pub fn evaluate_action(&mut self, mut action_list: Action) {
+    /// The state after the player to move plays `action`, seen from that player's perspective.
+    /// This is what the value function scores.
+    pub fn afterstate(&self, action: Action) -> GameState {
+        let mover = self.to_move;
+        let mut new_state = *self;
+        new_state.apply(action);
+        new_state.from_perspective(mover)
+    }
+
+    /// Mutate the current game state when player one plays out a full action sequence, without
+    /// passing the turn
+    fn evaluate_action(&mut self, mut action_list: Action) {
         // TODO: make this a proper iterator
         // for each action in action_list
         loop {
@@ -89,29 +323,29 @@ impl GameState {
 
     /// Mutate the current game state when playing out a single subaction
     fn evaluate_subaction(&mut self, subaction: SubAction) {
+        if self.rules.variant == Variant::Oware {
+            return self.evaluate_oware_subaction(subaction);
+        }
         let action = subaction as usize;
-        assert!(action != 6 && action != 13);
-        let seeds = self.houses[action] as usize;
+        let (store, opponent_store) = (self.store(), self.opponent_store());
+        assert!(action < store);
+        let seeds = self.houses[action];
         // Pickup seeds from starting house
         self.houses[action] = 0;
-        let end_house = action+seeds % 14;
-        // Deposit seeds in each house around the board
-        // Offset is to handle skipping of the opponents
-        // scoring house as we go around the loop
-        let mut offset = 0;
-        for i in action+1..end_house+1 {
-            if i > 0 && i % 13 == 0 {
-                self.houses[0] += 1;
-                offset += 1;
-            } else {
-                self.houses[(i+offset)%14] += 1;
-            }
+        // Deposit seeds in each house around the board, as many laps as it takes, skipping the
+        // opponent's scoring house
+        let mut end_house = action;
+        for _ in 0..seeds {
+            end_house = (end_house + 1) % opponent_store;
+            self.add_seeds(end_house, 1);
         }
         // Capture rule
-        if end_house < 6 && self.houses[end_house] == 1 {
+        if end_house < store && self.houses[end_house] == 1
+            && (self.rules.capture_empty_opposite || self.houses[2 * store - end_house] > 0) {
             // add to capture pile
-            let opposing_house = 12 - end_house;
-            self.houses[6] += 1 + self.houses[opposing_house];
+            let opposing_house = 2 * store - end_house;
+            let captured = 1 + self.houses[opposing_house];
+            self.add_seeds(store, captured);
             // clear houses on both sides
             self.houses[end_house] = 0;
             self.houses[opposing_house] = 0;
@@ -119,23 +353,97 @@ impl GameState {
         }
     }
 
-    /// Determine if subaction is 'renewing' and grants another turn
+    /// Sow a single Oware move: stores are skipped, as is the origin house on later laps, and
+    /// the last seed landing on the opponent's side making 2 or 3 captures that house along
+    /// with the unbroken run of 2s and 3s behind it.
+    fn evaluate_oware_subaction(&mut self, subaction: SubAction) {
+        let origin = subaction as usize;
+        let (store, opponent_store) = (self.store(), self.opponent_store());
+        assert!(origin < store);
+        let mut seeds = self.houses[origin];
+        self.houses[origin] = 0;
+        let mut house = origin;
+        while seeds > 0 {
+            house = (house + 1) % (opponent_store + 1);
+            if house == store || house == opponent_store || house == origin {
+                continue;
+            }
+            self.add_seeds(house, 1);
+            seeds -= 1;
+        }
+        if house < store {
+            return;
+        }
+        let mut first = house;
+        let mut captured = 0;
+        while first > store && (self.houses[first] == 2 || self.houses[first] == 3) {
+            captured += self.houses[first];
+            first -= 1;
+        }
+        // Grand slam: a capture that would take everything the opponent has captures nothing
+        let (_, opponent_tot) = self.side_totals();
+        if captured == 0 || captured as u32 == opponent_tot {
+            return;
+        }
+        for i in first+1..house+1 {
+            self.houses[i] = 0;
+        }
+        self.add_seeds(store, captured);
+        info!("Capture detected!");
+    }
+
+    /// Oware requires feeding an opponent who has run out of seeds whenever possible
+    fn is_legal_subaction(&self, sub: SubAction) -> bool {
+        if self.rules.variant == Variant::Kalah {
+            return true;
+        }
+        let (_, opponent_tot) = self.side_totals();
+        opponent_tot > 0 || self.houses[sub as usize] as usize + sub as usize >= self.pits()
+    }
+
+    /// Determine if subaction is 'renewing' and grants another turn, i.e. the last seed lands in
+    /// our store after however many laps of the board it takes
     fn is_renewing_subaction(&self, sub: SubAction) -> bool {
-        self.houses[sub as usize] + sub == 6
+        let seeds = self.houses[sub as usize] as usize;
+        let to_store = self.pits() - sub as usize;
+        let lap = self.opponent_store();
+        self.rules.extra_turns && seeds >= to_store && (seeds - to_store) % lap == 0
     }
 
+    /// Legal subactions for the player to move, numbered from their own side
+    pub fn legal_subactions(&self) -> Vec<SubAction> {
+        let view = self.from_perspective(self.to_move);
+        (0..self.pits as u8)
+            .filter(|&sub| view.houses[sub as usize] > 0 && view.is_legal_subaction(sub))
+            .collect()
+    }
+
+    /// Play a single subaction for the player to move. The turn passes unless the subaction
+    /// earns another one and there is something left to play with it, just as `gen_actions`
+    /// chains them.
+    pub fn apply_subaction(&mut self, sub: SubAction) {
+        let mover = self.to_move;
+        let mut view = self.from_perspective(mover);
+        let renewing = view.is_renewing_subaction(sub);
+        view.evaluate_subaction(sub);
+        if !renewing || view.find_next_subaction(0).is_none() {
+            view.to_move = PlayerTurn::P2;
+        }
+        *self = view.from_perspective(mover);
+    }
+
+    /// Every legal action for the player to move, with subactions numbered from their own side
     pub fn gen_actions(&self) -> ActionIter {
         ActionIter{ action: Action::new(),
-                    base_state: &self,
+                    base_state: self.from_perspective(self.to_move),
                     state_stack: Vec::new()
                   }
     }
 
     pub fn pick_action(self, epsilon: f64, values: &ValueFunction) -> (Action, f64) {
         let choices: Vec<(Action, f64)> = self.gen_actions()
-            .map(|action| (action, self.evaluate_to_new_state(action)))
-            .map(|(action, possible_state)| (action, *values.get(&possible_state)
-                                             .unwrap_or(&0.5f64)))
+            .map(|action| (action, self.afterstate(action)))
+            .map(|(action, possible_state)| (action, values.value(&possible_state)))
             .collect();
         info!("Actions available to choose from:");
         for action in &choices {
@@ -148,6 +456,7 @@ impl GameState {
         let mut best = &choices[0];
         if rand::random::<f64>() < epsilon {
             // randomly make a move
+            use rand::Rng;
             best = rand::thread_rng().choose(&choices).unwrap();
          } else {
             for choice in &choices {
@@ -159,9 +468,9 @@ impl GameState {
         best.clone()
     }
 
-    /// 'Rotate' the board so player one and two are swapped
+    /// 'Rotate' the board so player one and two are swapped, leaving the turn alone
     pub fn swap_board(&mut self) {
-        let n = self.houses.len();
+        let n = self.houses().len();
         for i in 0..n/2 {
             let temp = self.houses[i];
             self.houses[i] = self.houses[n/2+i];
@@ -170,8 +479,8 @@ impl GameState {
     }
 
     fn find_next_subaction(&self, search_start: SubAction) -> Option<SubAction> {
-        for index in search_start..6 {
-            if self.houses[index as usize] > 0 {
+        for index in search_start..self.pits as u8 {
+            if self.houses[index as usize] > 0 && self.is_legal_subaction(index) {
                 return Some(index)
             }
         }
@@ -179,13 +488,13 @@ impl GameState {
     }
 }
 
-struct ActionIter<'a> {
+pub struct ActionIter {
     action: Action,
-    base_state: &'a GameState,
+    base_state: GameState,
     state_stack: Vec<GameState>,
 }
 
-impl<'a> ActionIter<'a> {
+impl ActionIter {
     fn get_current_state(&self) -> GameState {
             if self.state_stack.is_empty() {
                 self.base_state.clone()
@@ -213,7 +522,7 @@ impl<'a> ActionIter<'a> {
     }
 }
 
-impl<'a> Iterator for ActionIter<'a> {
+impl Iterator for ActionIter {
     type Item = Action;
     fn next(&mut self) -> Option<Action> {
         // if action is empty: find terminal state and return
@@ -258,31 +567,32 @@ impl<'a> Iterator for ActionIter<'a> {
 
 impl Display for GameState {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (store, opponent_store) = (self.store(), self.opponent_store());
+        let border = format!("+{}+", "-".repeat(4 * store + 7));
         // upper row
-        try!(write!(f, "+-------------------------------+\n\
-                   |   |"));
+        try!(write!(f, "{}\n|   |", border));
         // player 2
-        for house in self.houses[7..13].iter().rev() {
+        for house in self.houses[store+1..opponent_store].iter().rev() {
             try!(write!(f, "{:2} |", house));
         }
         // end zones
-        try!(write!(f, "   |\n|{:2} |                       |{:2} |\n\
-                   |   |", self.houses[13], self.houses[6]));
+        try!(write!(f, "   |\n|{:2} |{}|{:2} |\n\
+                   |   |", self.houses[opponent_store], " ".repeat(4 * store - 1),
+                   self.houses[store]));
         // player 1
-        for house in &self.houses[0..6] {
+        for house in &self.houses[0..store] {
             try!(write!(f, "{:2} |", house));
         }
         // last line
-        write!(f, "   |\n+-------------------------------+\n")
+        write!(f, "   |\n{}\n", border)
     }
 }
 
-pub type ValueFunction = HashMap<GameState, f64>;
-
 #[cfg(test)]
 mod test {
     use super::*;
     use packed_actions::*;
+    use player::{Player, AIPlayer};
     use std::collections::HashMap;
     extern crate env_logger;
 
@@ -334,12 +644,17 @@ mod test {
         let action = Action::singleton(4);
         state.houses[4] = 10;
         state.evaluate_action(action);
-        for i in 5..13 {
+        for i in 7..11 {
             assert_eq!(state.houses[i], 1);
         }
+        assert_eq!(state.houses[5], 1);
+        assert_eq!(state.houses[12], 1);
         assert_eq!(state.houses[13], 0);
         assert_eq!(state.houses[0], 1);
-        assert_eq!(state.houses[1], 1);
+        // The last seed wraps around into the empty house 1 and captures
+        assert_eq!(state.houses[1], 0);
+        assert_eq!(state.houses[11], 0);
+        assert_eq!(state.houses[6], 3);
         assert_eq!(state.houses[2], 0);
         assert_eq!(state.houses[3], 0);
         assert_eq!(state.houses[4], 0);
@@ -352,7 +667,167 @@ mod test {
         let action = Action::singleton(0);
         state.evaluate_action(action);
         let expected: [u8; 14] = [0,5,5,5,0,4, 5, 4,0,4,4,4,4, 0];
-        assert_eq!(state.houses, expected);
+        assert_eq!(state.houses(), &expected);
+    }
+
+    #[test]
+    fn test_rule_set() {
+        // Without empty-opposite captures, landing in an empty house across from an empty house
+        // is just an ordinary move
+        let rules = RuleSet { capture_empty_opposite: false, .. RuleSet::kalah() };
+        let mut state = GameState::with_rules(4, rules);
+        state.houses[4] = 0;
+        state.houses[8] = 0;
+        state.evaluate_action(Action::singleton(0));
+        let expected: [u8; 14] = [0,5,5,5,1,4, 0, 4,0,4,4,4,4, 0];
+        assert_eq!(state.houses(), &expected);
+
+        // Landing in the store only grants another turn with extra turns enabled
+        let state = GameState::new(4);
+        assert!(state.is_renewing_subaction(2));
+        let rules = RuleSet { extra_turns: false, .. RuleSet::kalah() };
+        let state = GameState::with_rules(4, rules);
+        assert!(!state.is_renewing_subaction(2));
+        assert_eq!(state.gen_actions().count(), 6);
+
+        // Sweeping to the mover hands the opponent's remaining seeds to us
+        let rules = RuleSet { sweep_to_owner: false, .. RuleSet::kalah() };
+        let mut state = GameState::with_rules(0, rules);
+        state.houses[6] = 10;
+        state.houses[13] = 12;
+        state.houses[9] = 3;
+        // Player one made the last move
+        state.to_move = PlayerTurn::P2;
+        assert_eq!(state.is_won(), Some(P1Win));
+        state.rules = RuleSet::kalah();
+        assert_eq!(state.is_won(), Some(P2Win));
+    }
+
+    #[test]
+    fn test_oware_sowing() {
+        // A lap skips both stores and the house it started from
+        let mut state = GameState::with_rules(0, RuleSet::oware());
+        state.houses[1] = 12;
+        state.evaluate_action(Action::singleton(1));
+        let expected: [u8; 14] = [1,0,2,1,1,1, 0, 1,1,1,1,1,1, 0];
+        assert_eq!(state.houses(), &expected);
+
+        // Landing on a 3 captures it along with the 2s and 3s behind it, stopping at the 1
+        let mut state = GameState::with_rules(0, RuleSet::oware());
+        state.houses[3] = 5;
+        state.houses[8] = 1;
+        state.houses[9] = 2;
+        state.houses[11] = 4;
+        state.evaluate_action(Action::singleton(3));
+        let expected: [u8; 14] = [0,0,0,0,1,1, 5, 1,0,0,0,4,0, 0];
+        assert_eq!(state.houses(), &expected);
+
+        // Grand slam: capturing every opponent seed captures nothing
+        let mut state = GameState::with_rules(0, RuleSet::oware());
+        state.houses[5] = 2;
+        state.houses[7] = 1;
+        state.houses[8] = 2;
+        state.evaluate_action(Action::singleton(5));
+        let expected: [u8; 14] = [0,0,0,0,0,0, 0, 2,3,0,0,0,0, 0];
+        assert_eq!(state.houses(), &expected);
+    }
+
+    #[test]
+    fn test_oware_feeding() {
+        // With an empty opponent only the moves that reach them are legal
+        let mut state = GameState::with_rules(0, RuleSet::oware());
+        state.houses[0] = 2;
+        state.houses[3] = 3;
+        state.houses[4] = 1;
+        state.houses[6] = 10;
+        state.houses[13] = 12;
+        let actions = state.gen_actions().collect::<Vec<_>>();
+        assert_eq!(actions, vec![Action::singleton(3)]);
+
+        // If we can't feed them the game is over and we keep our seeds
+        state.houses[3] = 1;
+        assert_eq!(state.gen_actions().count(), 0);
+        assert!(state.is_ended());
+        assert_eq!(state.is_won(), Some(P1Win));
+
+        // A majority of captured seeds ends the game regardless of the board
+        let mut state = GameState::with_rules(4, RuleSet::oware());
+        assert!(!state.is_ended());
+        state.houses[..14].copy_from_slice(&[0,0,0,0,0,3, 25, 4,4,4,4,4,0, 0]);
+        assert!(state.is_ended());
+        assert_eq!(state.is_won(), Some(P1Win));
+    }
+
+    #[test]
+    fn test_board_sizes() {
+        let state = GameState::with_board(4, 3, RuleSet::kalah());
+        assert_eq!(state.houses(), &[3,3,3,3, 0, 3,3,3,3, 0]);
+        assert_eq!(state.gen_actions().count(), 6);
+        // (len: 1; 0,)
+        // (len: 2; 1,0,)
+        // (len: 2; 1,2,)
+        // (len: 2; 1,3,)
+        // (len: 1; 2,)
+        // (len: 1; 3,)
+
+        // Capture across the smaller board
+        let mut state = GameState::with_board(4, 3, RuleSet::kalah());
+        state.houses[3] = 0;
+        state.evaluate_action(Action::singleton(0));
+        assert_eq!(state.houses(), &[0,4,4,0, 4, 0,3,3,3, 0]);
+
+        let mut state = GameState::with_board(8, 2, RuleSet::kalah());
+        state.swap_board();
+        state.houses[0] = 5;
+        assert_eq!(state.houses[9], 2);
+        state.finalize_game();
+        assert_eq!(state.houses(), &[5,2,2,2,2,2,2,2, 0, 0,0,0,0,0,0,0,0, 16]);
+        assert_eq!(state.is_won(), Some(P1Win));
+        assert_eq!(format!("{}", state).lines().next().unwrap().len(), 4 * 8 + 9);
+    }
+
+    #[test]
+    fn test_multi_lap_sowing() {
+        // Twenty seeds lap the board once, skipping the opponent's store and refilling the
+        // starting house
+        let mut state = GameState::new(0);
+        state.houses[0] = 20;
+        state.evaluate_action(Action::singleton(0));
+        assert_eq!(state.houses(), &[1,2,2,2,2,2, 2, 2,1,1,1,1,1, 0]);
+
+        // Thirteen seeds come back around to the emptied starting house and capture
+        let mut state = GameState::new(0);
+        state.houses[2] = 13;
+        state.evaluate_action(Action::singleton(2));
+        assert_eq!(state.houses(), &[1,1,0,1,1,1, 3, 1,1,1,0,1,1, 0]);
+
+        // Landing in the store a lap later still earns another turn
+        let mut state = GameState::new(6);
+        state.houses[0] = 19;
+        assert!(state.is_renewing_subaction(0));
+        state.houses[0] = 20;
+        assert!(!state.is_renewing_subaction(0));
+    }
+
+    #[test]
+    fn test_wide_seed_totals() {
+        // 72 seeds overflowed the old u8 side sums once the stores were added in
+        let mut state = GameState::new(6);
+        for i in 0..6 {
+            state.houses[i] = 0;
+        }
+        state.houses[6] = 40;
+        state.houses[7] = 0;
+        state.houses[8] = 100;
+        assert_eq!(state.is_won(), Some(P2Win));
+        state.houses[6] = 150;
+        assert_eq!(state.is_won(), Some(P1Win));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_too_many_seeds() {
+        GameState::with_board(8, 16, RuleSet::kalah());
     }
 
     #[test]
@@ -360,18 +835,13 @@ mod test {
         let mut value_fun: HashMap<GameState, f64> = HashMap::new();
         let mut state = GameState::new(4);
         let action = Action::singleton(3);
-        let mut good_state = state.clone();
-        good_state.evaluate_action(action);
-        value_fun.insert(good_state, 10.0);
+        value_fun.insert(state.afterstate(action), 10.0);
         assert_eq!(state.pick_action(0.0, &value_fun).0, action);
-        // Now after performing that option and swapping the board, it should be a 
+        // Now after performing that option it's player two's turn, and it should be a 
         // different set of evaluations (ie: our value_fun info will not be useful 
         // for any of these particular actions)
-        state.evaluate_action(action);
-        state.swap_board();
-        let mut p2_good_state = state.clone();
-        p2_good_state.evaluate_action(Action::singleton(1));
-        value_fun.insert(p2_good_state, 4.0);
+        state.apply(action);
+        value_fun.insert(state.afterstate(Action::singleton(1)), 4.0);
         println!("{:?}", state.pick_action(0.0, &value_fun));
         assert_eq!(state.pick_action(0.0, &value_fun).0, Action::singleton(1));
 
@@ -426,15 +896,38 @@ mod test {
     }
 
     #[test]
-    fn test_player() {
+    fn test_apply() {
         let mut state = GameState::new(4);
-        let mut p1 = AIPlayer::new(state);
+        // Landing in our store keeps the turn within the action, then it passes
+        let mut action = Action::singleton(2);
+        action.push_front(0);
+        state.apply(action);
+        assert_eq!(state.to_move(), PlayerTurn::P2);
+        assert_eq!(state.houses(), &[0,5,1,6,6,5, 1, 4,4,4,4,4,4, 0]);
+
+        // Player two's subactions count from their own side of the board
+        assert_eq!(state.gen_actions().count(), state.from_perspective(PlayerTurn::P2).gen_actions().count());
+        state.apply(Action::singleton(5));
+        assert_eq!(state.to_move(), PlayerTurn::P1);
+        assert_eq!(state.houses(), &[1,6,2,6,6,5, 1, 4,4,4,4,4,0, 1]);
+        assert_eq!(state.afterstate(Action::singleton(0)).to_move(), PlayerTurn::P2);
+
+        let view = state.from_perspective(PlayerTurn::P2);
+        assert_eq!(view.to_move(), PlayerTurn::P2);
+        assert_eq!(view.houses(), &[4,4,4,4,4,0, 1, 1,6,2,6,6,5, 1]);
+        assert_eq!(view.from_perspective(PlayerTurn::P2), state);
+        assert_eq!(state.from_perspective(PlayerTurn::P1), state);
+    }
+
+    #[test]
+    fn test_player() {
+        let state = GameState::new(4);
+        let mut p1 = AIPlayer::new(PlayerTurn::P1, &state);
         let mut value_fun: HashMap<GameState, f64> = HashMap::new();
         let action = Action::singleton(4);
-        state.evaluate_action(action);
-        value_fun.insert(state, 10.0);
+        value_fun.insert(state.afterstate(action), 10.0);
 
-        assert_eq!(p1.take_action(&value_fun, 0.0), action);
+        assert_eq!(p1.take_action(&state, &value_fun, 0.0), action);
         p1.td_update(&mut value_fun, 0.2, 0.3);
     }
 }
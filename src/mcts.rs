@@ -0,0 +1,221 @@
+use std::time::{Duration, Instant};
+use rand::{self, Rng};
+use ::mancala::{GameState, Outcome, PlayerTurn};
+use ::packed_actions::Action;
+use ::player::Player;
+use ::value::ValueFunction;
+
+/// How long a rollout may run before it's scored as if the board were swept, which stops Oware
+/// rollouts cycling forever
+const MAX_ROLLOUT_ACTIONS: usize = 500;
+
+/// The usual UCT exploration constant, sqrt(2)
+pub const DEFAULT_EXPLORATION: f64 = 1.4142135623730951;
+
+/// How much searching to do for each move
+#[derive(Debug, Copy, Clone)]
+pub enum Budget {
+    Simulations(usize),
+    Time(Duration),
+}
+
+/// How to play out a position from a new leaf to the end of the game
+#[derive(Debug, Copy, Clone)]
+pub enum Rollout {
+    /// Uniformly random actions
+    Random,
+    /// Greedy on the value function, with actions taken at random this often
+    Guided(f64),
+}
+
+struct Node {
+    state: GameState,
+    /// The action that led here from the parent, and the parent
+    action: Option<Action>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Action>,
+    visits: u32,
+    /// Total reward for the player who moved into this node: 1 for a win, 0.5 for a tie
+    reward: f64,
+}
+
+impl Node {
+    fn new(state: GameState, action: Option<Action>, parent: Option<usize>) -> Node {
+        let untried = if state.is_ended() { Vec::new() } else { state.gen_actions().collect() };
+        Node { state: state, action: action, parent: parent, children: Vec::new(),
+               untried: untried, visits: 0, reward: 0.0 }
+    }
+}
+
+/// Reward for `player` if the game ended at `state`
+fn reward(state: &GameState, player: PlayerTurn) -> f64 {
+    let outcome = state.final_outcome();
+    let outcome = if player == PlayerTurn::P1 { outcome } else { outcome.reversed() };
+    match outcome {
+        Outcome::P1Win => 1.0,
+        Outcome::P2Win => 0.0,
+        Outcome::Tie => 0.5,
+    }
+}
+
+/// Plays with UCT: each simulation walks down the tree of explored actions picking children by
+/// their upper confidence bound, adds one new node, plays the game out with a rollout and
+/// credits the result back up the path. The most visited action at the root is played.
+pub struct MctsPlayer {
+    budget: Budget,
+    rollout: Rollout,
+    exploration: f64,
+}
+
+impl MctsPlayer {
+    pub fn new(budget: Budget, rollout: Rollout) -> MctsPlayer {
+        MctsPlayer { budget: budget, rollout: rollout, exploration: DEFAULT_EXPLORATION }
+    }
+
+    fn select_child(&self, nodes: &[Node], node: usize) -> usize {
+        let log_visits = (nodes[node].visits as f64).ln();
+        let ucb = |child: usize| {
+            let child = &nodes[child];
+            child.reward / child.visits as f64
+                + self.exploration * (log_visits / child.visits as f64).sqrt()
+        };
+        let mut best = nodes[node].children[0];
+        for &child in &nodes[node].children[1..] {
+            if ucb(child) > ucb(best) {
+                best = child;
+            }
+        }
+        best
+    }
+
+    fn play_out(&self, mut state: GameState, values: &ValueFunction) -> GameState {
+        let mut rng = rand::thread_rng();
+        for _ in 0..MAX_ROLLOUT_ACTIONS {
+            if state.is_ended() {
+                break;
+            }
+            let action = match self.rollout {
+                Rollout::Random => {
+                    let actions: Vec<Action> = state.gen_actions().collect();
+                    *rng.choose(&actions).unwrap()
+                }
+                Rollout::Guided(epsilon) => state.pick_action(epsilon, values).0,
+            };
+            state.apply(action);
+        }
+        state
+    }
+
+    /// Run one simulation from the root
+    fn simulate(&self, nodes: &mut Vec<Node>, values: &ValueFunction) {
+        // Selection
+        let mut node = 0;
+        while nodes[node].untried.is_empty() && !nodes[node].children.is_empty() {
+            node = self.select_child(nodes, node);
+        }
+        // Expansion
+        if let Some(action) = nodes[node].untried.pop() {
+            let mut state = nodes[node].state;
+            state.apply(action);
+            let child = nodes.len();
+            nodes.push(Node::new(state, Some(action), Some(node)));
+            nodes[node].children.push(child);
+            node = child;
+        }
+        // Rollout and backpropagation
+        let end = self.play_out(nodes[node].state, values);
+        let mut next = Some(node);
+        while let Some(node) = next {
+            let mover = nodes[node].state.to_move().other();
+            nodes[node].visits += 1;
+            nodes[node].reward += reward(&end, mover);
+            next = nodes[node].parent;
+        }
+    }
+
+    /// The most visited action from `state` after using up the budget, with its win rate
+    pub fn search(&self, state: &GameState, values: &ValueFunction) -> (Action, f64) {
+        let mut nodes = vec![Node::new(*state, None, None)];
+        let started = Instant::now();
+        let mut simulations = 0;
+        loop {
+            let done = match self.budget {
+                Budget::Simulations(limit) => simulations >= limit,
+                Budget::Time(limit) => started.elapsed() >= limit,
+            };
+            if done && simulations > 0 {
+                break;
+            }
+            self.simulate(&mut nodes, values);
+            simulations += 1;
+        }
+        debug!("Ran {} simulations, {} nodes", simulations, nodes.len());
+        let best = *nodes[0].children.iter()
+            .max_by_key(|&&child| nodes[child].visits)
+            .expect("no legal actions to search");
+        (nodes[best].action.unwrap(), nodes[best].reward / nodes[best].visits as f64)
+    }
+}
+
+impl Player for MctsPlayer {
+    fn name(&self) -> String {
+        match self.budget {
+            Budget::Simulations(n) => format!("MCTS ({} simulations)", n),
+            Budget::Time(limit) => format!("MCTS ({}ms)", limit.as_secs() * 1000 +
+                                                         limit.subsec_nanos() as u64 / 1_000_000),
+        }
+    }
+
+    fn take_action(&mut self,
+                   state: &GameState,
+                   values: &ValueFunction,
+                   _: f64) -> Action {
+        let (action, win_rate) = self.search(state, values);
+        debug!("MCTS picked action {} winning {}", action, win_rate);
+        action
+    }
+
+    fn td_update(&mut self,
+                 _: &mut ValueFunction,
+                 _: f64,
+                 _: f64) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use ::mancala::{GameState, RuleSet};
+    use ::notation::Position;
+    use ::packed_actions::{Action, ActionQueue};
+
+    #[test]
+    fn test_finds_win() {
+        // Capturing with the last seed in house 3 wins outright
+        let state = "1,0,1,0/3/5,0,0,0/4 1 kalah".parse::<Position>().unwrap().0;
+        let player = MctsPlayer::new(Budget::Simulations(500), Rollout::Random);
+        let (action, win_rate) = player.search(&state, &HashMap::new());
+        assert_eq!(action, Action::singleton(2));
+        assert!(win_rate > 0.9);
+
+        let player = MctsPlayer::new(Budget::Simulations(500), Rollout::Guided(0.1));
+        assert_eq!(player.search(&state, &HashMap::new()).0, Action::singleton(2));
+    }
+
+    #[test]
+    fn test_budgets() {
+        // A single legal action still gets searched and played
+        let state = "0,0,0,1/0/1,0,0,0/0 1 kalah".parse::<Position>().unwrap().0;
+        let player = MctsPlayer::new(Budget::Simulations(10), Rollout::Random);
+        assert_eq!(player.search(&state, &HashMap::new()).0, Action::singleton(3));
+
+        let state = GameState::with_rules(4, RuleSet::oware());
+        let mut player = MctsPlayer::new(Budget::Time(Duration::from_millis(50)), Rollout::Random);
+        let started = Instant::now();
+        let action = player.take_action(&state, &HashMap::new(), 0.0);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(state.gen_actions().any(|a| a == action));
+    }
+}
@@ -0,0 +1,149 @@
+use rand::{Rng, SeedableRng, XorShiftRng};
+use ::datafile::{self, Header};
+use ::mancala::GameState;
+use ::value::ValueFunction;
+
+/// Hidden units in a network unless asked otherwise
+pub const DEFAULT_HIDDEN_UNITS: usize = 32;
+
+/// Weights start out random, but the same every time so training runs can be repeated
+const INIT_SEED: [u32; 4] = [0x6d616e63, 0x616c6121, 0x2d6e6574, 0x776f726b];
+
+/// A fully connected network with one hidden layer of tanh units and a sigmoid output, valuing
+/// afterstates from the seeds in each house and store. Unlike the table it values every state,
+/// including ones never seen in training, and is trained a state at a time by plain SGD.
+#[derive(Debug, PartialEq, Clone, RustcDecodable, RustcEncodable)]
+pub struct Network {
+    pits: usize,
+    hidden: usize,
+    /// A row per hidden unit of one weight per input followed by the bias
+    hidden_weights: Vec<f64>,
+    /// One weight per hidden unit followed by the bias
+    output_weights: Vec<f64>,
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl Network {
+    /// A network for boards of `pits` houses a side with `hidden` hidden units
+    pub fn new(pits: usize, hidden: usize) -> Network {
+        let inputs = 2 * pits + 2;
+        let mut rng = XorShiftRng::from_seed(INIT_SEED);
+        let hidden_range = 1.0 / (inputs as f64).sqrt();
+        let output_range = 1.0 / (hidden as f64).sqrt();
+        let mut hidden_weights: Vec<f64> = (0..hidden * (inputs + 1))
+            .map(|_| rng.gen_range(-hidden_range, hidden_range))
+            .collect();
+        let mut output_weights: Vec<f64> = (0..hidden + 1)
+            .map(|_| rng.gen_range(-output_range, output_range))
+            .collect();
+        // Biases start at zero
+        for unit in 0..hidden {
+            hidden_weights[unit * (inputs + 1) + inputs] = 0.0;
+        }
+        output_weights[hidden] = 0.0;
+        Network { pits: pits, hidden: hidden, hidden_weights: hidden_weights,
+                  output_weights: output_weights }
+    }
+
+    /// Houses a side on the boards the network values
+    pub fn pits(&self) -> usize {
+        self.pits
+    }
+
+    fn inputs(&self) -> usize {
+        2 * self.pits + 2
+    }
+
+    /// Each house and store's share of the seeds on the board
+    fn features(&self, state: &GameState) -> Vec<f64> {
+        assert_eq!(state.pits(), self.pits, "network is for a different board size");
+        let total = state.houses().iter().map(|&h| h as f64).sum::<f64>().max(1.0);
+        state.houses().iter().map(|&h| h as f64 / total).collect()
+    }
+
+    /// Hidden unit activations and the output for `inputs`
+    fn forward(&self, inputs: &[f64]) -> (Vec<f64>, f64) {
+        let width = self.inputs() + 1;
+        let hidden: Vec<f64> = self.hidden_weights.chunks(width)
+            .map(|row| {
+                let sum = row[..width - 1].iter().zip(inputs).map(|(w, x)| w * x).sum::<f64>();
+                (sum + row[width - 1]).tanh()
+            })
+            .collect();
+        let sum = self.output_weights[..self.hidden].iter().zip(&hidden)
+            .map(|(w, h)| w * h).sum::<f64>();
+        let output = sigmoid(sum + self.output_weights[self.hidden]);
+        (hidden, output)
+    }
+}
+
+impl ValueFunction for Network {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        Some(self.forward(&self.features(state)).1)
+    }
+
+    /// One step of gradient descent on the squared error to `target`
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        let inputs = self.features(state);
+        let (hidden, output) = self.forward(&inputs);
+        // Error gradient with respect to the output unit's weighted sum
+        let output_delta = (output - target) * output * (1.0 - output);
+        let width = self.inputs() + 1;
+        for unit in 0..self.hidden {
+            let hidden_delta = output_delta * self.output_weights[unit]
+                * (1.0 - hidden[unit] * hidden[unit]);
+            let row = &mut self.hidden_weights[unit * width..(unit + 1) * width];
+            for (weight, input) in row.iter_mut().zip(&inputs) {
+                *weight -= learning_rate * hidden_delta * input;
+            }
+            row[width - 1] -= learning_rate * hidden_delta;
+            self.output_weights[unit] -= learning_rate * output_delta * hidden[unit];
+        }
+        self.output_weights[self.hidden] -= learning_rate * output_delta;
+    }
+
+    fn len(&self) -> usize {
+        self.hidden_weights.len() + self.output_weights.len()
+    }
+
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        datafile::save_values(self, header, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, RuleSet};
+    use ::packed_actions::Action;
+    use ::value::ValueFunction;
+
+    #[test]
+    fn test_untrained() {
+        let network = Network::new(4, 8);
+        let state = GameState::with_board(4, 3, RuleSet::kalah());
+        assert_eq!(network, Network::new(4, 8));
+        assert_eq!(network.len(), 8 * 11 + 9);
+        assert!(network.value(&state) > 0.0 && network.value(&state) < 1.0);
+        // Every state has a value, learned or not
+        assert!(network.get(&state).is_some());
+    }
+
+    #[test]
+    fn test_learns_targets() {
+        let mut network = Network::new(4, 8);
+        let start = GameState::with_board(4, 3, RuleSet::kalah());
+        let actions: Vec<Action> = start.gen_actions().collect();
+        let good = start.afterstate(actions[0]);
+        let bad = start.afterstate(actions[actions.len() - 1]);
+        for _ in 0..2000 {
+            network.update(&good, 0.9, 0.5);
+            network.update(&bad, 0.1, 0.5);
+        }
+        assert!((network.value(&good) - 0.9).abs() < 0.05, "{}", network.value(&good));
+        assert!((network.value(&bad) - 0.1).abs() < 0.05, "{}", network.value(&bad));
+    }
+}
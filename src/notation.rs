@@ -0,0 +1,178 @@
+use std::fmt::{self, Formatter, Display};
+use std::str::FromStr;
+use ::mancala::{GameState, PlayerTurn, RuleSet, Variant};
+
+/// A `GameState` written in position notation,
+///
+/// ```text
+/// 4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah
+/// ```
+///
+/// that is player one's houses and store, player two's houses and store, the side to move and
+/// the rules.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Position(pub GameState);
+
+fn write_houses(f: &mut Formatter, houses: &[u8]) -> fmt::Result {
+    for (i, house) in houses.iter().enumerate() {
+        if i > 0 {
+            try!(write!(f, ","));
+        }
+        try!(write!(f, "{}", house));
+    }
+    Ok(())
+}
+
+fn parse_seeds(s: &str) -> Result<u8, String> {
+    u8::from_str(s).map_err(|_| format!("invalid seed count {:?}", s))
+}
+
+fn parse_houses(s: &str) -> Result<Vec<u8>, String> {
+    s.split(',').map(parse_seeds).collect()
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let pits = self.0.pits();
+        let houses = self.0.houses();
+        try!(write_houses(f, &houses[..pits]));
+        try!(write!(f, "/{}/", houses[pits]));
+        try!(write_houses(f, &houses[pits+1..2*pits+1]));
+        let side = if self.0.to_move() == PlayerTurn::P1 { 1 } else { 2 };
+        write!(f, "/{} {} {}", houses[2*pits+1], side, self.0.rules())
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Position, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(format!("expected board, side to move and rules in {:?}", s));
+        }
+        let sections: Vec<&str> = fields[0].split('/').collect();
+        if sections.len() != 4 {
+            return Err(format!("expected houses/store/houses/store in {:?}", fields[0]));
+        }
+        let to_move = match fields[1] {
+            "1" => PlayerTurn::P1,
+            "2" => PlayerTurn::P2,
+            other => return Err(format!("side to move must be 1 or 2, not {:?}", other)),
+        };
+        let rules = try!(RuleSet::from_str(fields[2]));
+        let state = try!(GameState::from_sides(&try!(parse_houses(sections[0])),
+                                               try!(parse_seeds(sections[1])),
+                                               &try!(parse_houses(sections[2])),
+                                               try!(parse_seeds(sections[3])),
+                                               rules,
+                                               to_move));
+        Ok(Position(state))
+    }
+}
+
+/// Rules are written as the variant name followed by any departures from its defaults, e.g.
+/// `kalah+no-empty-capture+sweep-to-mover`.
+impl Display for RuleSet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let defaults = RuleSet::for_variant(self.variant);
+        try!(write!(f, "{}", match self.variant {
+            Variant::Kalah => "kalah",
+            Variant::Oware => "oware",
+        }));
+        if self.capture_empty_opposite != defaults.capture_empty_opposite {
+            try!(write!(f, "+{}", if self.capture_empty_opposite { "empty-capture" }
+                                  else { "no-empty-capture" }));
+        }
+        if self.sweep_to_owner != defaults.sweep_to_owner {
+            try!(write!(f, "+{}", if self.sweep_to_owner { "sweep-to-owner" }
+                                  else { "sweep-to-mover" }));
+        }
+        if self.extra_turns != defaults.extra_turns {
+            try!(write!(f, "+{}", if self.extra_turns { "extra-turns" } else { "no-extra-turns" }));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RuleSet, String> {
+        let mut tokens = s.split('+');
+        let mut rules = match tokens.next() {
+            Some("kalah") => RuleSet::kalah(),
+            Some("oware") => RuleSet::oware(),
+            _ => return Err(format!("unknown variant in rules {:?}", s)),
+        };
+        for token in tokens {
+            match token {
+                "empty-capture" => rules.capture_empty_opposite = true,
+                "no-empty-capture" => rules.capture_empty_opposite = false,
+                "sweep-to-owner" => rules.sweep_to_owner = true,
+                "sweep-to-mover" => rules.sweep_to_owner = false,
+                "extra-turns" => rules.extra_turns = true,
+                "no-extra-turns" => rules.extra_turns = false,
+                other => return Err(format!("unknown rule {:?}", other)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, PlayerTurn, RuleSet};
+
+    #[test]
+    fn test_round_trip() {
+        let position = Position(GameState::new(4));
+        assert_eq!(position.to_string(), "4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah");
+        assert_eq!(position.to_string().parse::<Position>(), Ok(position));
+
+        let notations = ["0,3,12,0/7/1,1,0,2/10 2 kalah+no-empty-capture+sweep-to-mover",
+                         "4,4,4,4,4,4,4,4/0/4,4,4,4,4,4,4,4/0 1 kalah+no-extra-turns",
+                         "0,0,0,2,5,1/20/1,3,0,0,0,4/12 2 oware"];
+        for notation in notations.iter() {
+            let position: Position = notation.parse().unwrap();
+            assert_eq!(&position.to_string(), notation);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules: RuleSet = "kalah+sweep-to-mover".parse().unwrap();
+        assert!(!rules.sweep_to_owner);
+        assert!(rules.extra_turns);
+        let rules: RuleSet = "oware+empty-capture".parse().unwrap();
+        assert_eq!(rules.to_string(), "oware+empty-capture");
+        assert!("kalah+no-extra-turns+sweep-to-owner".parse::<RuleSet>().unwrap().sweep_to_owner);
+        assert!("chess".parse::<RuleSet>().is_err());
+        assert!("kalah+sideways".parse::<RuleSet>().is_err());
+    }
+
+    #[test]
+    fn test_side_to_move() {
+        let position: Position = "1,2,3,4/5/6,7,8,9/10 2 kalah".parse().unwrap();
+        assert_eq!(position.0.to_move(), PlayerTurn::P2);
+        assert_eq!(position.0.houses(), &[1,2,3,4, 5, 6,7,8,9, 10]);
+        let view = position.0.from_perspective(PlayerTurn::P2);
+        assert_eq!(view.houses(), &[6,7,8,9, 10, 1,2,3,4, 5]);
+        assert_eq!(view.to_move(), PlayerTurn::P1);
+    }
+
+    #[test]
+    fn test_invalid_positions() {
+        let invalid = ["4,4,4,4,4,4/0/4,4,4,4,4,4/0 1",
+                       "4,4,4,4,4,4/0/4,4,4,4,4/0 1 kalah",
+                       "4,4,4/0/4,4,4/0 1 kalah",
+                       "4,4,4,4,4,4/0/4,4,4,4,4,4 1 kalah",
+                       "4,4,4,4,4,4/0/4,4,4,x,4,4/0 1 kalah",
+                       "4,4,4,4,4,4/0/4,4,4,4,4,4/0 3 kalah",
+                       "200,4,4,4/0/4,4,4,200/0 1 kalah"];
+        for notation in invalid.iter() {
+            assert!(notation.parse::<Position>().is_err(), "{} should not parse", notation);
+        }
+    }
+}
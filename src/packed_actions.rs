@@ -1,4 +1,5 @@
 use std::fmt::{self, Formatter, Display};
+use std::str::FromStr;
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub struct Action(u64);
@@ -19,6 +20,39 @@ impl Display for Action {
     }
 }
 
+impl Action {
+    /// Subactions in the order they are played
+    pub fn subactions(&self) -> Vec<SubAction> {
+        let mut copy = self.clone();
+        let mut subs = Vec::with_capacity(self.length() as usize);
+        while !copy.is_empty() {
+            subs.push(copy.pop_front());
+        }
+        subs
+    }
+
+    /// Compact form used in notation and game records, e.g. `2,0`
+    pub fn notation(&self) -> String {
+        self.subactions().iter().map(|sub| sub.to_string()).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Parses the compact `2,0` form written by `Action::notation`
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        let mut action = Action::new();
+        for sub in s.split(',') {
+            match SubAction::from_str(sub) {
+                Ok(sub) if sub < 8 && (action.length() as u64) < MAX_LEN => action.push_front(sub),
+                _ => return Err(format!("invalid action {:?}", s)),
+            }
+        }
+        Ok(action)
+    }
+}
+
 pub trait ActionQueue {
     fn push_front(&mut self, action: SubAction);
     fn pop_front(&mut self) -> SubAction;
@@ -47,7 +81,7 @@ impl ActionQueue for Action {
     
     /// Push subactions to the front of the deque
     fn push_front(&mut self, action: SubAction){
-        assert!(action < 7);
+        assert!(action < 8);
         let new_len = self.length() as u64 + 1u64;
         assert!(new_len <= MAX_LEN);
         self.0 = (self.0 & VEC_MASK) << VEC_EL_BITWIDTH | action as u64 | new_len << LEN_OFFSET;
@@ -102,6 +136,19 @@ mod test {
         assert!(action_list.is_empty());
     }
 
+    #[test]
+    fn test_notation() {
+        let mut action_list: Action = Action::new();
+        action_list.push_front(2);
+        action_list.push_front(0);
+        action_list.push_front(5);
+        assert_eq!(action_list.notation(), "2,0,5");
+        assert_eq!("2,0,5".parse::<Action>(), Ok(action_list));
+        assert_eq!("3".parse::<Action>(), Ok(Action::singleton(3)));
+        assert!("".parse::<Action>().is_err());
+        assert!("2,9".parse::<Action>().is_err());
+    }
+
     #[test]
     fn test_packed_internals() {
         let mut action_list: Action = Action::new();
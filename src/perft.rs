@@ -0,0 +1,120 @@
+use ::mancala::GameState;
+use ::packed_actions::Action;
+
+/// Number of positions exactly `depth` turns (full actions) from `state`. Games that finish
+/// sooner don't count.
+pub fn perft(state: &GameState, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if state.is_ended() {
+        return 0;
+    }
+    state.gen_actions()
+        .map(|action| { let mut next = *state; next.apply(action); perft(&next, depth - 1) })
+        .sum()
+}
+
+/// Like `perft` but counting each sub-action (a single sowing) as a ply of its own
+pub fn perft_subactions(state: &GameState, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if state.is_ended() {
+        return 0;
+    }
+    state.legal_subactions().into_iter()
+        .map(|sub| { let mut next = *state; next.apply_subaction(sub); perft_subactions(&next, depth - 1) })
+        .sum()
+}
+
+/// `perft` split by the first action, for tracking down where two move generators disagree
+pub fn divide(state: &GameState, depth: usize) -> Vec<(Action, u64)> {
+    if depth == 0 || state.is_ended() {
+        return Vec::new();
+    }
+    state.gen_actions()
+        .map(|action| { let mut next = *state; next.apply(action); (action, perft(&next, depth - 1)) })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::GameState;
+    use ::notation::Position;
+
+    /// Reference counts for standard positions: position, then perft by turn and by sub-action
+    /// for depths 1 and up. Any change to move generation or sowing that alters these needs to
+    /// explain why.
+    const REFERENCE: &'static [(&'static str, &'static [u64], &'static [u64])] = &[
+        ("4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah",
+         &[10, 116, 1022, 9682, 125678], &[6, 35, 185, 942, 4685]),
+        ("3,3,3,3,3,3/0/3,3,3,3,3,3/0 1 kalah",
+         &[10, 106, 818, 6819, 60579], &[6, 35, 182, 904, 4231]),
+        ("6,6,6,6,6,6/0/6,6,6,6,6,6/0 1 kalah",
+         &[10, 60, 329, 1907, 12441], &[6, 35, 190, 1056, 5882]),
+        ("3,3,3,3/0/3,3,3,3/0 1 kalah",
+         &[6, 40, 229, 911, 3901, 14881], &[4, 15, 50, 158, 488, 1507]),
+        ("4,4,4,4,4,4,4,4/0/4,4,4,4,4,4,4,4/0 1 kalah",
+         &[14, 206, 2328, 29057], &[8, 63, 453, 3169]),
+        ("4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah+no-extra-turns",
+         &[6, 36, 186, 964, 4758], &[6, 36, 186, 964, 4758]),
+        ("4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 oware",
+         &[6, 36, 190, 1014, 5219], &[6, 36, 190, 1014, 5219]),
+    ];
+
+    /// `perft` again, but built from single sub-actions instead of `gen_actions`, only counting
+    /// a ply when the turn passes
+    fn perft_by_subactions(state: &GameState, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        if state.is_ended() {
+            return 0;
+        }
+        state.legal_subactions().into_iter().map(|sub| {
+            let mut next = *state;
+            next.apply_subaction(sub);
+            if next.to_move() == state.to_move() {
+                perft_by_subactions(&next, depth)
+            } else {
+                perft_by_subactions(&next, depth - 1)
+            }
+        }).sum()
+    }
+
+    #[test]
+    fn test_reference() {
+        for &(notation, turns, subactions) in REFERENCE {
+            let state = notation.parse::<Position>().unwrap().0;
+            for (depth, &count) in turns.iter().enumerate() {
+                assert_eq!(perft(&state, depth + 1), count, "{} at depth {}", notation, depth + 1);
+            }
+            for (depth, &count) in subactions.iter().enumerate() {
+                assert_eq!(perft_subactions(&state, depth + 1), count,
+                           "{} at sub-action depth {}", notation, depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_action_iter_agrees() {
+        for &(notation, turns, _) in REFERENCE {
+            let state = notation.parse::<Position>().unwrap().0;
+            for depth in 1..turns.len().min(4) + 1 {
+                assert_eq!(perft(&state, depth), perft_by_subactions(&state, depth),
+                           "{} at depth {}", notation, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_divide() {
+        let state = GameState::new(4);
+        let divided = divide(&state, 3);
+        assert_eq!(divided.len(), state.gen_actions().count());
+        assert_eq!(divided.iter().map(|&(_, count)| count).sum::<u64>(), perft(&state, 3));
+        assert_eq!(perft(&state, 0), 1);
+    }
+}
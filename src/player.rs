@@ -1,106 +1,175 @@
-use std::collections::HashMap;
-use ::mancala::GameState;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use ::endgame::EndgameDb;
+use ::learning::{EligibilityTraces, Traces};
+use ::mancala::{GameState, PlayerTurn};
+use ::mancala::Outcome::*;
 use ::packed_actions::Action;
+use ::record::{GameRecord, MoveRecord};
+use ::value::ValueFunction;
 
+/// What a player decided to do when it was their turn
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Decision {
+    Play(Action),
+    /// Take back our last move along with the reply to it
+    Undo,
+    /// Put back the moves most recently taken back
+    Redo,
+}
+
+/// Something that can choose moves. Players are handed the one authoritative `GameState`
+/// whenever they need to look at the board rather than each keeping their own copy.
 pub trait Player {
-    pub fn opponent_plays(&mut self, action: Action);
-    pub fn current_state(&self) -> GameState;
-    pub fn take_action(&mut self,
-                   values: &HashMap<GameState, f64>,
+    /// Name recorded for this player in game records
+    fn name(&self) -> String;
+    /// Told the state after the opponent has moved
+    fn opponent_plays(&mut self, _state: &GameState) {}
+    fn take_action(&mut self,
+                   state: &GameState,
+                   values: &ValueFunction,
                    epsilon: f64) -> Action;
-    pub fn td_update(&self,
-                 values: &mut HashMap<GameState, f64>,
+    /// Like `take_action`, but players that support it may ask to undo or redo moves instead
+    fn take_turn(&mut self,
+                 state: &GameState,
+                 values: &ValueFunction,
+                 epsilon: f64) -> Decision {
+        Decision::Play(self.take_action(state, values, epsilon))
+    }
+    fn td_update(&mut self,
+                 values: &mut ValueFunction,
                  learning_rate: f64,
                  discount_factor: f64);
 }
 
-const DEFAULT_STATE_VAL: f64 = 0.5f64;
-
+/// Plays from the value function, remembering the last two states it saw (from its own
+/// perspective) for TD updates, along with eligibility traces over the states it has left
+/// behind this game. Given an endgame database it plays perfectly once the position is small
+/// enough to be in it.
 pub struct AIPlayer {
+    side: PlayerTurn,
     curr_state: GameState,
     last_state: GameState,
+    traces: EligibilityTraces,
+    /// The state most recently given a trace, so each state we leave is only marked once
+    traced_state: Option<GameState>,
+    endgame: Option<EndgameDb>,
 }
 
 
 impl AIPlayer {
-    fn new(starting_state: GameState) -> AIPlayer {
-        AIPlayer { curr_state: starting_state.clone(),
-                 last_state: starting_state.clone() }
+    pub fn new(side: PlayerTurn, starting_state: &GameState) -> AIPlayer {
+        let view = starting_state.from_perspective(side);
+        AIPlayer { side: side,
+                   curr_state: view,
+                   last_state: view,
+                   traces: EligibilityTraces::new(0.0, Traces::Accumulating),
+                   traced_state: None,
+                   endgame: None }
+    }
+
+    /// Do TD(lambda) updates instead of the default one-step TD(0)
+    pub fn with_traces(self, lambda: f64, kind: Traces) -> AIPlayer {
+        AIPlayer { traces: EligibilityTraces::new(lambda, kind), .. self }
+    }
+
+    pub fn with_endgame(self, endgame: EndgameDb) -> AIPlayer {
+        AIPlayer { endgame: Some(endgame), .. self }
+    }
+
+    /// The most recent state we've seen, from our perspective
+    pub fn current_state(&self) -> GameState {
+        self.curr_state
     }
 }
 
 impl Player for AIPlayer {
-    fn opponent_plays(&mut self, action: Action) {
+    fn name(&self) -> String {
+        "Computer".to_string()
+    }
+
+    fn opponent_plays(&mut self, state: &GameState) {
         self.last_state = self.curr_state;
-        self.curr_state.swap_board();
-        self.curr_state.evaluate_action(action);
-        self.curr_state.swap_board();
+        self.curr_state = state.from_perspective(self.side);
     }
 
     fn take_action(&mut self,
-                   values: &HashMap<GameState, f64>,
+                   state: &GameState,
+                   values: &ValueFunction,
                    epsilon: f64) -> Action {
-        let (action, _) = self.curr_state.pick_action(epsilon, values);
-        debug!("Picked action {} at state \n{}", action, self.curr_state);
-        self.curr_state.evaluate_action(action);
+        let action = match self.endgame.as_ref().and_then(|endgame| endgame.best_action(state)) {
+            Some((action, margin)) => {
+                debug!("Endgame database says {} wins by {}", action, margin);
+                action
+            }
+            None => state.pick_action(epsilon, values).0,
+        };
+        debug!("Picked action {} at state \n{}", action, state);
+        self.curr_state = state.afterstate(action);
         debug!("Evaluated action {}, now at state\n{}", action, self.curr_state);
         action
     }
 
-    fn td_update(&self,
-                 values: &mut HashMap<GameState, f64>,
+    fn td_update(&mut self,
+                 values: &mut ValueFunction,
                  learning_rate: f64,
                  discount_factor: f64) {
-        let q_next = *values.entry(self.curr_state).or_insert(DEFAULT_STATE_VAL);
-        let q_last = values.entry(self.last_state).or_insert(DEFAULT_STATE_VAL);
-        let q_tmp = *q_last; // just for printing
-        *q_last += learning_rate * (discount_factor * q_next - q_tmp);
+        let q_next = values.value(&self.curr_state);
+        let q_last = values.value(&self.last_state);
+        let delta = discount_factor * q_next - q_last;
+        if self.traced_state != Some(self.last_state) {
+            self.traces.visit(self.last_state, discount_factor);
+            self.traced_state = Some(self.last_state);
+        }
+        self.traces.update(values, learning_rate, delta);
         debug!("Doing TD update from (self.last_state) q_last:\n{}\n\
                to (self.curr_state) q_next:\n{}",
                self.last_state, self.curr_state);
         debug!("q_last += learning_rate * (discount_factor * q_next - q_last)\n\
             {} += {} * ({} * {} - {})",
-            *q_last, learning_rate, discount_factor, q_next, q_tmp);
-
-    }
-
-    fn current_state(&self) -> GameState {
-        self.curr_state
+            values.value(&self.last_state), learning_rate, discount_factor, q_next, q_last);
     }
 }
 
-pub struct HumanPlayer{
-    curr_state: GameState
-}
+pub struct HumanPlayer;
 
 impl HumanPlayer {
-    fn new(starting_state: GameState) -> HumanPlayer {
-        HumanPlayer { curr_state: starting_state.clone() }
+    pub fn new() -> HumanPlayer {
+        HumanPlayer
     }
 }
 
 impl Player for HumanPlayer {
-    fn opponent_plays(&mut self, action: Action) {
-        self.curr_state.swap_board();
-        self.curr_state.evaluate_action(action);
-        self.curr_state.swap_board();
+    fn name(&self) -> String {
+        "Human".to_string()
     }
 
     fn take_action(&mut self,
-                   values: &HashMap<GameState, f64>,
-                   _: f64) -> Action {
-        println!("Computer went. State now (from your perspective):\n{}", self.curr_state);
+                   state: &GameState,
+                   values: &ValueFunction,
+                   epsilon: f64) -> Action {
+        loop {
+            if let Decision::Play(action) = self.take_turn(state, values, epsilon) {
+                return action;
+            }
+        }
+    }
+
+    fn take_turn(&mut self,
+                 state: &GameState,
+                 values: &ValueFunction,
+                 _: f64) -> Decision {
+        println!("Computer went. State now (from your perspective):\n{}",
+                 state.from_perspective(state.to_move()));
         println!("\n----------------\n");
         println!("Now considering your options: ");
-        for action in self.curr_state.gen_actions() {
-            let mut state = self.curr_state;
-            state.evaluate_action(action);
-            println!("\n----------------\n{}:\n{}\nqval: {:?}\n", action, state, values.get(&state));
+        for action in state.gen_actions() {
+            let next = state.afterstate(action);
+            println!("\n----------------\n{}:\n{}\nqval: {:?}\n", action, next, values.get(&next));
         }
 
-        let choices: Vec<Action> = self.curr_state.gen_actions().collect();
+        let choices: Vec<Action> = state.gen_actions().collect();
         let index = loop {
-            println!("Choose from these options:");
+            println!("Choose from these options (or type undo or redo):");
             for (i, choice) in choices.iter().enumerate() {
                 println!("\t({}): {}", i, choice);
             }
@@ -110,6 +179,11 @@ impl Player for HumanPlayer {
             if let Err(_) = stdin().read_line(&mut input) {
                 continue;
             }
+            match input.trim() {
+                "undo" => return Decision::Undo,
+                "redo" => return Decision::Redo,
+                _ => {}
+            }
             if let Ok(index) = u8::from_str(&input.trim()) {
                 if (index as usize) < choices.len() {
                     break index
@@ -118,42 +192,178 @@ impl Player for HumanPlayer {
         };
 
         let action = choices[index as usize];
-        debug!("Picked action {} at state \n{}", action, self.curr_state);
-        self.curr_state.evaluate_action(action);
-        debug!("Evaluated action {}, now at state\n{}", action, self.curr_state);
-        println!("You played. State now:\n{}", self.curr_state);
-        action
+        debug!("Picked action {} at state \n{}", action, state);
+        println!("You played. State now:\n{}", state.afterstate(action));
+        Decision::Play(action)
     }
 
-    fn td_update(&self,
-                 _: &mut HashMap<GameState, f64>,
+    fn td_update(&mut self,
+                 _: &mut ValueFunction,
                  _: f64,
                  _: f64) {}
+}
 
-    fn current_state(&self) -> GameState {
-        self.curr_state
-    }
+pub fn side_index(side: PlayerTurn) -> usize {
+    if side == PlayerTurn::P1 { 0 } else { 1 }
 }
 
-pub fn play_loop(mut p1: Box<Player>, mut p2: Box<Player>,
-             values: &mut HashMap<GameState, f64>,
-             starting_state: GameState) {
+/// Play a game between `p1` (on the player one side) and `p2` starting from `start`, returning
+/// the record of the game.
+///
+/// Either player may ask to undo their last move (and the reply to it) or redo what was undone.
+pub fn play_loop(p1: Box<Player>, p2: Box<Player>,
+             values: &mut ValueFunction,
+             start: GameState) -> GameRecord {
+    let date = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut record = GameRecord::new([p1.name(), p2.name()], date, start);
+    let mut players = [p1, p2];
+    let mut state = start;
+    // The state before each move in the record
+    let mut history: Vec<GameState> = Vec::new();
+    let mut undone: Vec<MoveRecord> = Vec::new();
     println!("Starting play loop:");
-    println!("Starting state:\n{}", p1.current_state());
+    println!("Starting state:\n{}", state);
     loop {
-        let action = p1.take_action(values, 0.0);
-        p2.opponent_plays(action);
-        if p1.current_state().is_ended() {
+        let turn = side_index(state.to_move());
+        let started = Instant::now();
+        let decision = players[turn].take_turn(&state, values, 0.0);
+        let elapsed = started.elapsed();
+        let mv = match decision {
+            Decision::Play(action) => {
+                undone.clear();
+                MoveRecord {
+                    side: state.to_move(),
+                    action: action,
+                    elapsed_ms: elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000,
+                    value: values.get(&state.afterstate(action)),
+                }
+            }
+            Decision::Undo => {
+                if history.len() < 2 {
+                    println!("Nothing to undo");
+                    continue;
+                }
+                for _ in 0..2 {
+                    undone.push(record.moves.pop().unwrap());
+                    state = history.pop().unwrap();
+                }
+                println!("Took back your last move and the reply to it");
+                continue;
+            }
+            Decision::Redo => {
+                if undone.len() < 2 {
+                    println!("Nothing to redo");
+                    continue;
+                }
+                for _ in 0..2 {
+                    let mv = undone.pop().unwrap();
+                    history.push(state);
+                    state.apply(mv.action);
+                    players[side_index(state.to_move())].opponent_plays(&state);
+                    record.moves.push(mv);
+                }
+                println!("Put back your move and the reply to it");
+                continue;
+            }
+        };
+        history.push(state);
+        state.apply(mv.action);
+        players[1 - turn].opponent_plays(&state);
+        record.moves.push(mv);
+        if state.is_ended() {
             break;
         }
-        std::mem::swap(&mut p1, &mut p2);
     }
-    println!("Game ended at state (from your perspective):\n{}", p1.current_state());
-    match p1.current_state().is_won() {
-        Some(P1Win) => println!("You won!"),
-        Some(P2Win) => println!("You Lost!"),
+    println!("Game ended at state:\n{}", state);
+    record.result = state.is_won();
+    match record.result {
+        Some(P1Win) => println!("{} won!", record.players[0]),
+        Some(P2Win) => println!("{} won!", record.players[1]),
         Some(Tie) => println!("Tied!?!"),
         _ => println!("Not over yet?"),
     }
+    record
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use ::mancala::GameState;
+    use ::notation::Position;
+    use ::packed_actions::{Action, ActionQueue};
+
+    /// Plays back a fixed list of decisions, then the first legal action once they run out
+    struct ScriptedPlayer {
+        decisions: Vec<Decision>,
+    }
+
+    impl Player for ScriptedPlayer {
+        fn name(&self) -> String {
+            "Script".to_string()
+        }
+
+        fn take_action(&mut self, _: &GameState, _: &ValueFunction, _: f64) -> Action {
+            unreachable!()
+        }
+
+        fn take_turn(&mut self, state: &GameState, _: &ValueFunction, _: f64) -> Decision {
+            let decision = if self.decisions.is_empty() {
+                Decision::Play(state.gen_actions().next().unwrap())
+            } else {
+                self.decisions.remove(0)
+            };
+            if let Decision::Play(action) = decision {
+                assert!(state.gen_actions().any(|a| a == action));
+            }
+            decision
+        }
+
+        fn td_update(&mut self, _: &mut ValueFunction, _: f64, _: f64) {}
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let start: Position = "2,2,2,2/0/2,2,2,2/0 1 kalah".parse().unwrap();
+        let p1 = ScriptedPlayer {
+            decisions: vec![Decision::Undo, Decision::Play(Action::singleton(0)), Decision::Undo,
+                            Decision::Redo, Decision::Redo, Decision::Undo, Decision::Redo],
+        };
+        let p2 = ScriptedPlayer {
+            decisions: vec![Decision::Play(Action::singleton(3))],
+        };
+
+        let record = play_loop(Box::new(p1), Box::new(p2), &mut HashMap::new(), start.0);
+        assert_eq!(record.moves[0].action, Action::singleton(0));
+        assert_eq!(record.moves[1].action, Action::singleton(3));
+        // Replaying from scratch only works if the driver's state stayed in step with the record
+        let (_, last) = record.states().unwrap();
+        assert!(last.is_ended());
+        assert_eq!(record.result, Some(last.final_outcome()));
+    }
+
+    #[test]
+    fn test_ai_player_views() {
+        let state = GameState::new(4);
+        let mut p1 = AIPlayer::new(PlayerTurn::P1, &state);
+        let mut p2 = AIPlayer::new(PlayerTurn::P2, &state);
+        assert_eq!(p2.current_state(), state.from_perspective(PlayerTurn::P2));
+
+        let action = p1.take_action(&state, &HashMap::new(), 0.0);
+        assert_eq!(p1.current_state(), state.afterstate(action));
+        let mut next = state;
+        next.apply(action);
+        p2.opponent_plays(&next);
+        assert_eq!(p2.current_state().to_move(), PlayerTurn::P1);
+        assert_eq!(p2.current_state(), next.from_perspective(PlayerTurn::P2));
+    }
+
+    #[test]
+    fn test_ai_player_endgame() {
+        // Greedy on an empty table would just play the first action rather than the winning capture
+        let state: GameState = "1,0,1,0/3/5,0,0,0/4 1 kalah".parse::<Position>().unwrap().0;
+        let endgame = EndgameDb::solve(4, state.rules(), 7);
+        let mut player = AIPlayer::new(PlayerTurn::P1, &state).with_endgame(endgame);
+        assert_eq!(player.take_action(&state, &HashMap::new(), 0.0), Action::singleton(2));
+    }
 }
-    
@@ -0,0 +1,243 @@
+use std::fmt::{self, Formatter, Display};
+use std::str::FromStr;
+use ::mancala::{GameState, Outcome, PlayerTurn};
+use ::notation::Position;
+use ::packed_actions::Action;
+use ::value::ValueFunction;
+
+/// One move of a recorded game
+#[derive(Debug, PartialEq, Clone)]
+pub struct MoveRecord {
+    pub side: PlayerTurn,
+    pub action: Action,
+    /// How long the player took to decide, in milliseconds
+    pub elapsed_ms: u64,
+    /// The value function's opinion of the resulting state, from the mover's point of view
+    pub value: Option<f64>,
+}
+
+/// A complete game, written as a handful of PGN-style tag lines followed by one line per move:
+///
+/// ```text
+/// [Player1 "Human"]
+/// [Player2 "Computer"]
+/// [Date "1497052800"]
+/// [Position "4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah"]
+/// [Result "0-1"]
+/// 1 2,0 5120 0.61
+/// 2 3 2 -
+/// ```
+///
+/// Each move line is the side that moved, the action, the time taken in milliseconds and the
+/// value of the resulting state (or `-` when it has none).
+#[derive(Debug, PartialEq, Clone)]
+pub struct GameRecord {
+    pub players: [String; 2],
+    /// Seconds since the unix epoch when the game started
+    pub date: u64,
+    pub start: GameState,
+    pub moves: Vec<MoveRecord>,
+    /// Outcome from player one's point of view, or None if the game was not finished
+    pub result: Option<Outcome>,
+}
+
+impl GameRecord {
+    pub fn new(players: [String; 2], date: u64, start: GameState) -> GameRecord {
+        GameRecord {
+            players: players,
+            date: date,
+            start: start,
+            moves: Vec::new(),
+            result: None,
+        }
+    }
+
+    /// The state before each move paired with that move, with the final state at the end
+    pub fn states(&self) -> Result<(Vec<(GameState, &MoveRecord)>, GameState), String> {
+        let mut states = Vec::with_capacity(self.moves.len());
+        let mut state = self.start;
+        for (i, mv) in self.moves.iter().enumerate() {
+            if mv.side != state.to_move() {
+                return Err(format!("move {} was played out of turn", i + 1));
+            }
+            if !state.gen_actions().any(|action| action == mv.action) {
+                return Err(format!("move {} ({}) is not legal", i + 1, mv.action.notation()));
+            }
+            states.push((state, mv));
+            state.apply(mv.action);
+        }
+        Ok((states, state))
+    }
+}
+
+/// Step through a recorded game, showing each board and what `values` thinks of every move that
+/// was available
+pub fn replay(record: &GameRecord, values: &ValueFunction) {
+    println!("{} vs {}, starting from {}", record.players[0], record.players[1],
+             Position(record.start));
+    let (states, last) = match record.states() {
+        Ok(states) => states,
+        Err(e) => {
+            println!("Can't replay record: {}", e);
+            return;
+        }
+    };
+    for (i, &(state, mv)) in states.iter().enumerate() {
+        let side = if mv.side == PlayerTurn::P1 { 0 } else { 1 };
+        println!("\n----------------\nMove {}: {} to play (from their perspective):\n{}",
+                 i + 1, record.players[side], state.from_perspective(mv.side));
+        for action in state.gen_actions() {
+            let next = state.afterstate(action);
+            let marker = if action == mv.action { "*" } else { " " };
+            println!("  {} {:12} qval: {:?}", marker, action.notation(), values.get(&next));
+        }
+        println!("Played {} after {}ms", mv.action.notation(), mv.elapsed_ms);
+    }
+    println!("\n----------------\nFinal state:\n{}", last);
+    println!("Result: {}", result_notation(&record.result));
+}
+
+fn parse_tag(line: &str) -> Result<(&str, &str), String> {
+    if !line.ends_with(']') {
+        return Err(format!("malformed tag {:?}", line));
+    }
+    let inner = &line[1..line.len()-1];
+    let space = try!(inner.find(' ').ok_or(format!("malformed tag {:?}", line)));
+    let (name, value) = inner.split_at(space);
+    Ok((name, value.trim().trim_matches('"')))
+}
+
+fn result_notation(result: &Option<Outcome>) -> &'static str {
+    match *result {
+        Some(Outcome::P1Win) => "1-0",
+        Some(Outcome::P2Win) => "0-1",
+        Some(Outcome::Tie) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        try!(writeln!(f, "[Player1 \"{}\"]", self.players[0]));
+        try!(writeln!(f, "[Player2 \"{}\"]", self.players[1]));
+        try!(writeln!(f, "[Date \"{}\"]", self.date));
+        try!(writeln!(f, "[Position \"{}\"]", Position(self.start)));
+        try!(writeln!(f, "[Result \"{}\"]", result_notation(&self.result)));
+        for mv in &self.moves {
+            let side = if mv.side == PlayerTurn::P1 { 1 } else { 2 };
+            try!(write!(f, "{} {} {} ", side, mv.action.notation(), mv.elapsed_ms));
+            try!(match mv.value {
+                Some(value) => writeln!(f, "{}", value),
+                None => writeln!(f, "-"),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GameRecord, String> {
+        let mut players = [String::new(), String::new()];
+        let mut date = 0;
+        let mut start = None;
+        let mut result = None;
+        let mut moves = Vec::new();
+        for line in s.lines().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+            if line.starts_with('[') {
+                let (name, value) = try!(parse_tag(line));
+                match name {
+                    "Player1" => players[0] = value.to_string(),
+                    "Player2" => players[1] = value.to_string(),
+                    "Date" => date = try!(value.parse().map_err(|_| format!("bad date {:?}", value))),
+                    "Position" => start = Some(try!(value.parse::<Position>()).0),
+                    "Result" => result = match value {
+                        "1-0" => Some(Outcome::P1Win),
+                        "0-1" => Some(Outcome::P2Win),
+                        "1/2-1/2" => Some(Outcome::Tie),
+                        "*" => None,
+                        _ => return Err(format!("unknown result {:?}", value)),
+                    },
+                    _ => return Err(format!("unknown tag {:?}", name)),
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return Err(format!("malformed move {:?}", line));
+            }
+            moves.push(MoveRecord {
+                side: match fields[0] {
+                    "1" => PlayerTurn::P1,
+                    "2" => PlayerTurn::P2,
+                    _ => return Err(format!("malformed move {:?}", line)),
+                },
+                action: try!(fields[1].parse()),
+                elapsed_ms: try!(fields[2].parse().map_err(|_| format!("malformed move {:?}", line))),
+                value: match fields[3] {
+                    "-" => None,
+                    value => Some(try!(value.parse().map_err(|_| format!("malformed move {:?}", line)))),
+                },
+            });
+        }
+        let start = try!(start.ok_or("record has no Position tag".to_string()));
+        Ok(GameRecord {
+            players: players,
+            date: date,
+            start: start,
+            moves: moves,
+            result: result,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, Outcome, PlayerTurn};
+    use ::notation::Position;
+    use ::packed_actions::{Action, ActionQueue};
+
+    #[test]
+    fn test_round_trip() {
+        let start = GameState::new(4);
+        let mut record = GameRecord::new(["Human".to_string(), "Computer".to_string()], 1497052800, start);
+        let mut two_zero = Action::singleton(2);
+        two_zero.push_front(0);
+        record.moves.push(MoveRecord { side: PlayerTurn::P1, action: two_zero, elapsed_ms: 5120, value: Some(0.61) });
+        record.moves.push(MoveRecord { side: PlayerTurn::P2, action: Action::singleton(3), elapsed_ms: 2, value: None });
+        record.result = Some(Outcome::P2Win);
+        let text = record.to_string();
+        assert_eq!(text, "[Player1 \"Human\"]\n\
+                          [Player2 \"Computer\"]\n\
+                          [Date \"1497052800\"]\n\
+                          [Position \"4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 kalah\"]\n\
+                          [Result \"0-1\"]\n\
+                          1 2,0 5120 0.61\n\
+                          2 3 2 -\n");
+        assert_eq!(text.parse::<GameRecord>(), Ok(record));
+    }
+
+    #[test]
+    fn test_states() {
+        let start = "4,4,4,4,4,4/0/4,4,4,4,4,4/0 2 kalah".parse::<Position>().unwrap().0;
+        let mut record = GameRecord::new(["a".to_string(), "b".to_string()], 0, start);
+        record.moves.push(MoveRecord { side: PlayerTurn::P2, action: Action::singleton(0), elapsed_ms: 0, value: None });
+        record.moves.push(MoveRecord { side: PlayerTurn::P1, action: Action::singleton(1), elapsed_ms: 0, value: None });
+        let (states, last) = record.states().unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].0, start);
+        assert_eq!(states[1].0.houses(), &[4,4,4,4,4,4, 0, 0,5,5,5,5,4, 0]);
+        assert_eq!(states[1].0.to_move(), PlayerTurn::P1);
+        assert_eq!(last.houses(), &[4,0,5,5,5,5, 0, 0,5,5,5,5,4, 0]);
+        assert_eq!(last.to_move(), PlayerTurn::P2);
+
+        // Out of turn and illegal moves are rejected
+        record.moves[1].side = PlayerTurn::P2;
+        assert!(record.states().is_err());
+        record.moves[1].side = PlayerTurn::P1;
+        record.moves[1].action = Action::singleton(6);
+        assert!(record.states().is_err());
+    }
+}
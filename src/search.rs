@@ -0,0 +1,366 @@
+use std::f64;
+use std::time::{Duration, Instant};
+use ::endgame::EndgameDb;
+use ::mancala::{GameState, Outcome, PlayerTurn, RuleSet};
+use ::packed_actions::Action;
+use ::player::Player;
+use ::transposition::{Bound, Entry, TranspositionTable, DEFAULT_TABLE_ENTRIES};
+use ::value::ValueFunction;
+
+/// Score given to a won game, well clear of anything an evaluator returns
+pub const WIN_SCORE: f64 = 1000.0;
+
+/// Scores a position for the player to move; higher is better for them
+pub trait Evaluator {
+    fn evaluate(&self, state: &GameState) -> f64;
+}
+
+/// Seeds in our store less seeds in the opponent's
+pub struct StoreDifference;
+
+impl Evaluator for StoreDifference {
+    fn evaluate(&self, state: &GameState) -> f64 {
+        let view = state.from_perspective(state.to_move());
+        let pits = view.pits();
+        view.houses()[pits] as f64 - view.houses()[2 * pits + 1] as f64
+    }
+}
+
+/// Scores positions with a learned value function, scaled to -1 (lost) to 1 (won)
+pub struct TableEvaluator {
+    values: Box<ValueFunction>,
+}
+
+impl TableEvaluator {
+    pub fn new(values: Box<ValueFunction>) -> TableEvaluator {
+        TableEvaluator { values: values }
+    }
+}
+
+impl Evaluator for TableEvaluator {
+    fn evaluate(&self, state: &GameState) -> f64 {
+        // The table holds afterstates, valued for the player who just moved
+        let last_mover = state.to_move().other();
+        let key = state.from_perspective(last_mover);
+        let value = self.values.value(&key);
+        1.0 - 2.0 * value
+    }
+}
+
+/// Score of a finished game for the player to move
+fn terminal_score(state: &GameState) -> f64 {
+    let outcome = state.final_outcome();
+    let outcome = if state.to_move() == PlayerTurn::P1 { outcome } else { outcome.reversed() };
+    match outcome {
+        Outcome::P1Win => WIN_SCORE,
+        Outcome::P2Win => -WIN_SCORE,
+        Outcome::Tie => 0.0,
+    }
+}
+
+/// Deepest a timed search will go
+pub const MAX_SEARCH_DEPTH: usize = 64;
+
+/// How often, in nodes, the search looks at the clock
+const NODES_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Plays the action with the best negamax score, searching with iterative deepening until it
+/// reaches its depth or runs out of time. Results are kept in a transposition table between
+/// iterations and moves, and the best action found for a position is searched first when it
+/// comes up again. Ties go to the first action searched so play is deterministic at a fixed
+/// depth.
+pub struct SearchPlayer {
+    depth: usize,
+    time_limit: Option<Duration>,
+    evaluator: Box<Evaluator>,
+    table: TranspositionTable,
+    /// Board size and rules of the positions in the table, which doesn't tell games apart
+    board: Option<(usize, RuleSet)>,
+    endgame: Option<EndgameDb>,
+    deadline: Option<Instant>,
+    nodes: u64,
+    stopped: bool,
+}
+
+impl SearchPlayer {
+    /// A player that always searches `depth` actions deep
+    pub fn new(depth: usize, evaluator: Box<Evaluator>) -> SearchPlayer {
+        SearchPlayer { depth: depth,
+                       time_limit: None,
+                       evaluator: evaluator,
+                       table: TranspositionTable::new(DEFAULT_TABLE_ENTRIES),
+                       board: None,
+                       endgame: None,
+                       deadline: None,
+                       nodes: 0,
+                       stopped: false }
+    }
+
+    /// A player that searches as deep as it can in `time_limit` per move
+    pub fn timed(time_limit: Duration, evaluator: Box<Evaluator>) -> SearchPlayer {
+        SearchPlayer { time_limit: Some(time_limit),
+                       .. SearchPlayer::new(MAX_SEARCH_DEPTH, evaluator) }
+    }
+
+    /// Look up positions the endgame database covers instead of searching them
+    pub fn with_endgame(self, endgame: EndgameDb) -> SearchPlayer {
+        SearchPlayer { endgame: Some(endgame), .. self }
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if !self.stopped && self.nodes % NODES_PER_CLOCK_CHECK == 0 {
+            if let Some(deadline) = self.deadline {
+                self.stopped = Instant::now() >= deadline;
+            }
+        }
+        self.stopped
+    }
+
+    /// Legal actions with `first` (if it's one of them) moved to the front
+    fn ordered_actions(state: &GameState, first: Option<Action>) -> Vec<Action> {
+        let mut actions: Vec<Action> = state.gen_actions().collect();
+        if let Some(first) = first {
+            if let Some(i) = actions.iter().position(|&action| action == first) {
+                let action = actions.remove(i);
+                actions.insert(0, action);
+            }
+        }
+        actions
+    }
+
+    /// Negamax value of `state` for the player to move, searched `depth` actions deep. Scores
+    /// outside `alpha..beta` are only bounds. The result is meaningless once `stopped` is set.
+    fn negamax(&mut self, state: &GameState, depth: usize, mut alpha: f64, beta: f64) -> f64 {
+        if self.out_of_time() {
+            return 0.0;
+        }
+        if state.is_ended() {
+            return terminal_score(state);
+        }
+        if let Some(margin) = self.endgame.as_ref().and_then(|endgame| endgame.margin(state)) {
+            return WIN_SCORE * margin.signum() as f64;
+        }
+        if depth == 0 {
+            return self.evaluator.evaluate(state);
+        }
+        let hash = self.table.hash(state);
+        let mut first = None;
+        if let Some(entry) = self.table.probe(hash) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.score,
+                    Bound::Lower if entry.score >= beta => return entry.score,
+                    Bound::Upper if entry.score <= alpha => return entry.score,
+                    _ => {}
+                }
+            }
+            first = Some(entry.best);
+        }
+
+        let original_alpha = alpha;
+        let mut best = f64::NEG_INFINITY;
+        let mut best_action = None;
+        for action in SearchPlayer::ordered_actions(state, first) {
+            let mut next = *state;
+            next.apply(action);
+            let score = -self.negamax(&next, depth - 1, -beta, -alpha);
+            if self.stopped {
+                return 0.0;
+            }
+            if score > best {
+                best = score;
+                best_action = Some(action);
+            }
+            if best > alpha {
+                alpha = best;
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.store(Entry { hash: hash, depth: depth, score: best, bound: bound,
+                                 best: best_action.unwrap() });
+        best
+    }
+
+    /// Search every action at the root `depth` deep, trying `first` first
+    fn search_root(&mut self, state: &GameState, depth: usize, first: Option<Action>)
+                   -> Option<(Action, f64)> {
+        let mut best = None;
+        let mut alpha = f64::NEG_INFINITY;
+        for action in SearchPlayer::ordered_actions(state, first) {
+            let mut next = *state;
+            next.apply(action);
+            let score = -self.negamax(&next, depth - 1, f64::NEG_INFINITY, -alpha);
+            if self.stopped {
+                return None;
+            }
+            debug!("Search scored {} at {} to depth {}", action, score, depth);
+            if best.is_none() || score > alpha {
+                best = Some(action);
+                alpha = score;
+            }
+        }
+        best.map(|action| (action, alpha))
+    }
+
+    /// The best action for the player to move and its score, from the deepest search that
+    /// finished in time
+    pub fn search(&mut self, state: &GameState) -> (Action, f64) {
+        let board = Some((state.pits(), state.rules()));
+        if self.board != board {
+            self.table.clear();
+            self.board = board;
+        }
+        self.deadline = self.time_limit.map(|limit| Instant::now() + limit);
+        self.stopped = false;
+        self.nodes = 0;
+        let mut best = None;
+        for depth in 1..self.depth.max(1) + 1 {
+            match self.search_root(state, depth, best.map(|(action, _)| action)) {
+                Some(result) => best = Some(result),
+                None => break,
+            }
+            // Nothing deeper to learn once the game is decided
+            if best.unwrap().1.abs() >= WIN_SCORE {
+                break;
+            }
+        }
+        match best {
+            Some(best) => best,
+            // Out of time before the first iteration finished, so anything legal will do
+            None => (state.gen_actions().next().expect("no legal actions to search"), 0.0),
+        }
+    }
+}
+
+impl Player for SearchPlayer {
+    fn name(&self) -> String {
+        match self.time_limit {
+            Some(limit) => format!("Search ({}ms)", limit.as_secs() * 1000 +
+                                                    limit.subsec_nanos() as u64 / 1_000_000),
+            None => format!("Search (depth {})", self.depth),
+        }
+    }
+
+    fn take_action(&mut self,
+                   state: &GameState,
+                   _: &ValueFunction,
+                   _: f64) -> Action {
+        let (action, score) = self.search(state);
+        debug!("Search picked action {} with score {}", action, score);
+        action
+    }
+
+    fn td_update(&mut self,
+                 _: &mut ValueFunction,
+                 _: f64,
+                 _: f64) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::f64;
+    use std::time::{Duration, Instant};
+    use ::mancala::{GameState, PlayerTurn, RuleSet};
+    use ::notation::Position;
+    use ::packed_actions::{Action, ActionQueue};
+
+    fn minimax(state: &GameState, depth: usize, evaluator: &Evaluator) -> f64 {
+        if state.is_ended() {
+            return terminal_score(state);
+        }
+        if depth == 0 {
+            return evaluator.evaluate(state);
+        }
+        state.gen_actions().map(|action| {
+            let mut next = *state;
+            next.apply(action);
+            -minimax(&next, depth - 1, evaluator)
+        }).fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    #[test]
+    fn test_store_difference() {
+        let position: Position = "1,0,0,0/5/0,0,2,0/3 2 kalah".parse().unwrap();
+        assert_eq!(StoreDifference.evaluate(&position.0), -2.0);
+        assert_eq!(StoreDifference.evaluate(&position.0.from_perspective(PlayerTurn::P2)), -2.0);
+    }
+
+    #[test]
+    fn test_search_matches_minimax() {
+        let positions = ["3,3,3,3/0/3,3,3,3/0 1 kalah",
+                         "0,2,5,1/4/1,0,3,2/6 2 kalah",
+                         "4,4,4,4,4,4/0/4,4,4,4,4,4/0 1 oware"];
+        for notation in positions.iter() {
+            let state = notation.parse::<Position>().unwrap().0;
+            for depth in 1..5 {
+                let mut player = SearchPlayer::new(depth, Box::new(StoreDifference));
+                assert_eq!(player.search(&state).1, minimax(&state, depth, &StoreDifference),
+                           "{} at depth {}", notation, depth);
+            }
+        }
+        // Small enough to search to the end, where transpositions can't make a difference
+        let state = "1,2,0,1/0/2,0,1,1/0 1 kalah".parse::<Position>().unwrap().0;
+        let mut player = SearchPlayer::new(MAX_SEARCH_DEPTH, Box::new(StoreDifference));
+        assert_eq!(player.search(&state).1, minimax(&state, MAX_SEARCH_DEPTH, &StoreDifference));
+        // And the table remembers it for the next search
+        assert_eq!(player.search(&state).1, minimax(&state, MAX_SEARCH_DEPTH, &StoreDifference));
+
+        // The table is forgotten for a game with other rules, which could reach the same houses
+        let mut player = SearchPlayer::new(4, Box::new(StoreDifference));
+        let kalah = "3,3,3,3/0/3,3,3,3/0 1 kalah".parse::<Position>().unwrap().0;
+        let mut next = kalah;
+        next.apply(player.search(&kalah).0);
+        let hash = player.table.hash(&next);
+        assert!(player.table.probe(hash).is_some());
+        player.search(&"3,3,3,3/0/3,3,3,3/0 1 oware".parse::<Position>().unwrap().0);
+        assert!(player.table.probe(hash).is_none());
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let state = GameState::with_board(8, 6, RuleSet::kalah());
+        let mut player = SearchPlayer::timed(Duration::from_millis(50), Box::new(StoreDifference));
+        let started = Instant::now();
+        let (action, _) = player.search(&state);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(state.gen_actions().any(|a| a == action));
+    }
+
+    #[test]
+    fn test_finds_win() {
+        // Capturing with the last seed in house 3 takes the opponent's 5 and wins outright
+        let state = "0,0,1,0/3/5,0,0,0/2 1 kalah".parse::<Position>().unwrap().0;
+        let mut player = SearchPlayer::new(1, Box::new(StoreDifference));
+        assert_eq!(player.take_action(&state, &HashMap::new(), 0.0), Action::singleton(2));
+        assert!(player.search(&state).1 >= WIN_SCORE);
+        // And search deeper still sees it
+        let mut player = SearchPlayer::new(4, Box::new(StoreDifference));
+        assert_eq!(player.search(&state).0, Action::singleton(2));
+    }
+
+    #[test]
+    fn test_table_evaluator() {
+        let state = GameState::new(4);
+        let action = Action::singleton(0);
+        let mut values = HashMap::new();
+        values.insert(state.afterstate(action), 1.0);
+        let evaluator = TableEvaluator::new(Box::new(values));
+        let mut next = state;
+        next.apply(action);
+        // A sure win for player one is a sure loss for player two, now to move
+        assert_eq!(evaluator.evaluate(&next), -1.0);
+        assert_eq!(evaluator.evaluate(&state), 0.0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use ::endgame::{key, swept_margin};
+use ::mancala::{GameState, PlayerTurn, Variant};
+use ::packed_actions::Action;
+
+/// Solves whole Kalah games by exhaustive search, remembering every position it has solved.
+/// Like the endgame database positions are remembered with empty stores, valued by how many
+/// more of the seeds still in play the player to move ends up with under perfect play.
+///
+/// Only positions reachable from the ones asked about are solved, which keeps boards like
+/// 4 pits of 3 seeds tractable. A game always either takes seeds off the board or moves them
+/// along the mover's side, so the search can't loop.
+pub struct GameSolver {
+    values: HashMap<GameState, i16>,
+}
+
+fn stores(state: &GameState) -> i32 {
+    let view = state.from_perspective(state.to_move());
+    let pits = view.pits();
+    view.houses()[pits] as i32 - view.houses()[2 * pits + 1] as i32
+}
+
+impl GameSolver {
+    pub fn new() -> GameSolver {
+        GameSolver { values: HashMap::new() }
+    }
+
+    /// Number of positions solved so far
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Final store difference for the player to move under perfect play by both sides
+    pub fn margin(&mut self, state: &GameState) -> i32 {
+        assert!(state.rules().variant == Variant::Kalah, "only Kalah games can be solved");
+        if state.is_ended() {
+            return swept_margin(state);
+        }
+        let key = key(state);
+        if let Some(&remaining) = self.values.get(&key) {
+            return stores(state) + remaining as i32;
+        }
+        let remaining = key.gen_actions()
+            .map(|action| { let mut next = key; next.apply(action); -self.margin(&next) })
+            .max()
+            .unwrap();
+        self.values.insert(key, remaining as i16);
+        stores(state) + remaining
+    }
+
+    /// The best action for the player to move with the margin it leads to, or None if the game
+    /// is over. Ties go to the first action generated.
+    pub fn best_action(&mut self, state: &GameState) -> Option<(Action, i32)> {
+        if state.is_ended() {
+            return None;
+        }
+        let mut best: Option<(Action, i32)> = None;
+        for action in state.gen_actions() {
+            let mut next = *state;
+            next.apply(action);
+            let margin = -self.margin(&next);
+            if best.map_or(true, |(_, best_margin)| margin > best_margin) {
+                best = Some((action, margin));
+            }
+        }
+        best
+    }
+
+    /// The actions both sides play from `state` to the end of the game under perfect play
+    pub fn principal_variation(&mut self, state: &GameState) -> Vec<Action> {
+        let mut variation = Vec::new();
+        let mut state = *state;
+        while let Some((action, _)) = self.best_action(&state) {
+            variation.push(action);
+            state.apply(action);
+        }
+        variation
+    }
+}
+
+/// How a learned value function measures up against the solved game
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comparison {
+    /// Learned states that belong to the solved game
+    pub states: usize,
+    /// Mean squared error of the learned values against the true result (1 for a win, 0.5 for
+    /// a tie, 0 for a loss) for the player the state is seen from
+    pub mean_squared_error: f64,
+    /// Fraction of those states where playing greedily on the values from the position after
+    /// them keeps the best result available
+    pub optimal_moves: f64,
+}
+
+/// Compare `values` against perfect play for every learned state that can be played under
+/// the same board and rules as `start`
+pub fn compare(values: &HashMap<GameState, f64>, solver: &mut GameSolver, start: &GameState) -> Comparison {
+    let mut states = 0;
+    let mut squared_error = 0.0;
+    let mut optimal = 0;
+    for (state, &value) in values {
+        if state.pits() != start.pits() || state.rules() != start.rules() {
+            continue;
+        }
+        states += 1;
+        // Learned states are seen from player one's side, whoever is to move
+        let margin = solver.margin(state);
+        let margin = if state.to_move() == PlayerTurn::P1 { margin } else { -margin };
+        let truth = if margin > 0 { 1.0 } else if margin < 0 { 0.0 } else { 0.5 };
+        squared_error += (value - truth) * (value - truth);
+
+        match solver.best_action(state) {
+            Some((_, best)) => {
+                let mut next = *state;
+                next.apply(state.pick_action(0.0, values).0);
+                if (-solver.margin(&next)).signum() == best.signum() {
+                    optimal += 1;
+                }
+            }
+            None => optimal += 1,
+        }
+    }
+    Comparison {
+        states: states,
+        mean_squared_error: if states > 0 { squared_error / states as f64 } else { 0.0 },
+        optimal_moves: if states > 0 { optimal as f64 / states as f64 } else { 0.0 },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use ::endgame::EndgameDb;
+    use ::mancala::{GameState, RuleSet};
+    use ::notation::Position;
+    use ::search::{SearchPlayer, StoreDifference, MAX_SEARCH_DEPTH};
+
+    #[test]
+    fn test_matches_endgame_db() {
+        let db = EndgameDb::solve(4, RuleSet::kalah(), 7);
+        let mut solver = GameSolver::new();
+        for notation in ["1,0,1,0/3/5,0,0,0/4 1 kalah", "0,2,1,0/9/0,1,1,1/10 1 kalah",
+                         "2,0,0,1/1/0,1,2,0/0 2 kalah"].iter() {
+            let state = notation.parse::<Position>().unwrap().0;
+            assert_eq!(Some(solver.margin(&state)), db.margin(&state), "{}", notation);
+        }
+    }
+
+    #[test]
+    fn test_small_game() {
+        let start = GameState::with_board(4, 2, RuleSet::kalah());
+        let mut solver = GameSolver::new();
+        let margin = solver.margin(&start);
+        // Search to the end agrees on who wins
+        let mut search = SearchPlayer::new(MAX_SEARCH_DEPTH, Box::new(StoreDifference));
+        assert_eq!(search.search(&start).1.signum(), (margin as f64).signum());
+
+        // Playing out the principal variation gets exactly the solved margin
+        let variation = solver.principal_variation(&start);
+        let mut state = start;
+        for &action in &variation {
+            state.apply(action);
+        }
+        assert!(state.is_ended());
+        let final_margin = swept_margin(&state);
+        let margin_for_start = if variation.len() % 2 == 0 { final_margin } else { -final_margin };
+        assert_eq!(margin_for_start, margin);
+    }
+
+    #[test]
+    fn test_compare() {
+        let start = GameState::with_board(4, 1, RuleSet::kalah());
+        let mut solver = GameSolver::new();
+        let (best, margin) = solver.best_action(&start).unwrap();
+        let mut values = HashMap::new();
+        let truth = if margin > 0 { 1.0 } else if margin < 0 { 0.0 } else { 0.5 };
+        values.insert(start.afterstate(best), truth);
+        // States from other boards are ignored
+        values.insert(GameState::new(4), 0.5);
+        let comparison = compare(&values, &mut solver, &start);
+        assert_eq!(comparison.states, 1);
+        assert_eq!(comparison.mean_squared_error, 0.0);
+    }
+}
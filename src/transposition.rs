@@ -0,0 +1,152 @@
+use ::mancala::{GameState, PlayerTurn, MAX_PITS, MAX_TOTAL_SEEDS};
+use ::packed_actions::Action;
+
+/// Random keys for each possible seed count in each house, and for player two being to move.
+/// A state's hash is the xor of the keys that apply to it.
+pub struct Zobrist {
+    houses: Vec<Vec<u64>>,
+    p2_to_move: u64,
+}
+
+/// splitmix64, so the keys are the same on every run without pulling in a seeded rng
+fn next_key(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+impl Zobrist {
+    pub fn new() -> Zobrist {
+        let mut seed = 0;
+        let houses = (0..2 * MAX_PITS + 2)
+            .map(|_| (0..MAX_TOTAL_SEEDS + 1).map(|_| next_key(&mut seed)).collect())
+            .collect();
+        Zobrist { houses: houses, p2_to_move: next_key(&mut seed) }
+    }
+
+    pub fn hash(&self, state: &GameState) -> u64 {
+        let mut hash = if state.to_move() == PlayerTurn::P2 { self.p2_to_move } else { 0 };
+        for (house, &seeds) in state.houses().iter().enumerate() {
+            hash ^= self.houses[house][seeds as usize];
+        }
+        hash
+    }
+}
+
+/// How a stored score relates to the true value of its position
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Bound {
+    Exact,
+    /// The search failed high, the value is at least this
+    Lower,
+    /// The search failed low, the value is at most this
+    Upper,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Entry {
+    pub hash: u64,
+    /// How many actions deep the score was searched
+    pub depth: usize,
+    pub score: f64,
+    pub bound: Bound,
+    pub best: Action,
+}
+
+/// A fixed number of search results, indexed by Zobrist hash. A new result replaces whatever
+/// was in its slot unless that was a deeper search of the same position.
+///
+/// The hash leaves out the board size and rules, so a table should only be used for one game.
+pub struct TranspositionTable {
+    zobrist: Zobrist,
+    entries: Vec<Option<Entry>>,
+}
+
+/// Default number of slots, about 12MB worth
+pub const DEFAULT_TABLE_ENTRIES: usize = 1 << 18;
+
+impl TranspositionTable {
+    /// A table with `entries` slots, rounded up to a power of two
+    pub fn new(entries: usize) -> TranspositionTable {
+        TranspositionTable { zobrist: Zobrist::new(),
+                             entries: vec![None; entries.next_power_of_two()] }
+    }
+
+    pub fn hash(&self, state: &GameState) -> u64 {
+        self.zobrist.hash(state)
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        (hash as usize) & (self.entries.len() - 1)
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        match self.entries[self.slot(hash)] {
+            Some(entry) if entry.hash == hash => Some(entry),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let slot = self.slot(entry.hash);
+        if let Some(old) = self.entries[slot] {
+            if old.hash == entry.hash && old.depth > entry.depth {
+                return;
+            }
+        }
+        self.entries[slot] = Some(entry);
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::mancala::{GameState, PlayerTurn};
+    use ::notation::Position;
+    use ::packed_actions::{Action, ActionQueue};
+
+    #[test]
+    fn test_zobrist() {
+        let zobrist = Zobrist::new();
+        let state = GameState::new(4);
+        assert_eq!(zobrist.hash(&state), Zobrist::new().hash(&state));
+        // The hash depends only on the state, however it was reached
+        let mut a = state;
+        a.apply(Action::singleton(0));
+        a.apply(Action::singleton(0));
+        let b = Position(a).to_string().parse::<Position>().unwrap().0;
+        assert_eq!(zobrist.hash(&a), zobrist.hash(&b));
+        assert!(zobrist.hash(&a) != zobrist.hash(&state));
+        // The side to move is part of the hash
+        let flipped = state.from_perspective(PlayerTurn::P2);
+        assert_eq!(flipped.houses(), state.houses());
+        assert!(zobrist.hash(&flipped) != zobrist.hash(&state));
+    }
+
+    #[test]
+    fn test_table() {
+        let mut table = TranspositionTable::new(1000);
+        let state = GameState::new(4);
+        let hash = table.hash(&state);
+        assert_eq!(table.probe(hash), None);
+        let entry = Entry { hash: hash, depth: 4, score: 1.5, bound: Bound::Exact,
+                            best: Action::singleton(2) };
+        table.store(entry);
+        assert_eq!(table.probe(hash), Some(entry));
+        // A shallower result doesn't replace a deeper one of the same position
+        table.store(Entry { depth: 2, .. entry });
+        assert_eq!(table.probe(hash), Some(entry));
+        table.store(Entry { depth: 6, .. entry });
+        assert_eq!(table.probe(hash).unwrap().depth, 6);
+        table.clear();
+        assert_eq!(table.probe(hash), None);
+    }
+}
@@ -0,0 +1,526 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use ::compact::CompactTable;
+use ::datafile::{self, Header};
+use ::learning::TrainingHistory;
+use ::linear::Linear;
+use ::mancala::{GameState, PlayerTurn, RuleSet};
+use ::network::Network;
+
+/// What a state never learned is worth unless a run says otherwise: no more likely a win than
+/// a loss
+pub const DEFAULT_STATE_VAL: f64 = 0.5f64;
+
+/// The initial value for optimistic runs, which makes every untried move look like a win and so
+/// gets each of them tried before settling on one
+pub const OPTIMISTIC_STATE_VAL: f64 = 1.0;
+
+/// Estimates how good an afterstate is for the player who just moved into it, from 0 (lost) to
+/// 1 (won). Afterstates are seen from that player's perspective.
+pub trait ValueFunction {
+    /// The estimate for `state`, or None if it has never been learned
+    fn get(&self, state: &GameState) -> Option<f64>;
+
+    /// The estimate for `state`, falling back on the default for states never learned
+    fn value(&self, state: &GameState) -> f64 {
+        self.get(state).unwrap_or(DEFAULT_STATE_VAL)
+    }
+
+    /// Move the estimate for `state` a step of `learning_rate` towards `target`
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64);
+
+    /// Count a visit to `state` by training, for value functions that keep track
+    fn visit(&mut self, _: &GameState) {}
+
+    /// How many numbers have been learned: states for a table, weights for an approximation
+    fn len(&self) -> usize;
+
+    /// Write to the training datafile at `path` after `header`, to be read back by `load`
+    fn save(&self, path: &str, header: &Header) -> Result<(), String>;
+}
+
+/// The kinds of value function that can be trained and saved
+#[derive(Debug, Eq, PartialEq, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub enum Model {
+    Table,
+    Network,
+    Linear,
+    /// A table saved as a `CompactTable`
+    Compact,
+}
+
+/// Read the value function in the training datafile at `path`, whichever kind its header says
+/// it is, checking it was trained for the board `state` is on
+pub fn load(path: &str, state: &GameState) -> Result<Box<ValueFunction>, String> {
+    // Compact tables are mapped rather than read, so never touch more of them than the header
+    let (header, mut input) = try!(datafile::open_values(path));
+    try!(header.check(path, state));
+    Ok(match header.model {
+        Model::Table => Box::new(try!(decode_table(path, &header, &mut input))),
+        Model::Network => Box::new(try!(datafile::decode_values::<Network>(path, &mut input))),
+        Model::Linear => Box::new(try!(datafile::decode_values::<Linear>(path, &mut input))),
+        Model::Compact => Box::new(try!(CompactTable::open(path)).1),
+    })
+}
+
+/// Decode the values of a `Model::Table` datafile from `input`, opened by `open_values`
+fn decode_table(path: &str, header: &Header, input: &mut BufReader<File>) -> Result<Table, String> {
+    // Tables didn't count visits before version 2
+    if header.version < 2 {
+        let values = try!(datafile::decode_values(path, input));
+        Ok(Table { values: values, visits: HashMap::new() })
+    } else {
+        let (values, visits) = try!(datafile::decode_values(path, input));
+        Ok(Table { values: values, visits: visits })
+    }
+}
+
+/// Read the table in the training datafile at `path` with its header, whichever way it was saved
+pub fn read_table(path: &str) -> Result<(Header, Table), String> {
+    let (header, mut input) = try!(datafile::open_values(path));
+    match header.model {
+        Model::Table => {
+            let table = try!(decode_table(path, &header, &mut input));
+            Ok((header, table))
+        }
+        Model::Compact => {
+            let (header, compact) = try!(CompactTable::open(path));
+            let table = try!(compact.to_table());
+            Ok((header, table))
+        }
+        model => Err(format!("{} holds {:?} values, not a table", path, model)),
+    }
+}
+
+/// Read the table in the training datafile at `path`, checking it was trained for the board
+/// `state` is on
+pub fn load_table(path: &str, state: &GameState) -> Result<Table, String> {
+    let (header, table) = try!(read_table(path));
+    try!(header.check(path, state));
+    Ok(table)
+}
+
+/// A state as tables were keyed before boards and rules could vary: 6 pit Kalah, with the side
+/// of the player who just moved first and no record of whose turn it is
+#[derive(Eq, PartialEq, Hash, RustcDecodable, RustcEncodable)]
+struct LegacyState {
+    houses: [u8; 14],
+}
+
+impl LegacyState {
+    /// The same afterstate as tables key it now, with the opponent to move
+    fn state(&self) -> Result<GameState, String> {
+        let h = &self.houses;
+        GameState::from_sides(&h[..6], h[6], &h[7..13], h[13], RuleSet::kalah(), PlayerTurn::P2)
+    }
+}
+
+/// Add a header to a training datafile from before they had one, which holds `model` values
+/// trained on the board `state` is on. The history that used to be kept in a datafile of its
+/// own moves into the header.
+pub fn migrate(path: &str, model: Model, state: &GameState) -> Result<Header, String> {
+    if datafile::load_header(path).is_ok() {
+        return Err(format!("{} already has a header", path));
+    }
+    let history_path = format!("{}.history", path);
+    let has_history = Path::new(&history_path).exists();
+    let history = if has_history {
+        try!(datafile::load(&history_path))
+    } else {
+        TrainingHistory::new()
+    };
+    let header = Header::new(model, state, history);
+    match model {
+        Model::Table | Model::Compact => {
+            if state.pits() != 6 || state.rules() != RuleSet::kalah() {
+                return Err(format!("{} holds a table for 6 pit {}, not {} pit {}", path,
+                                   RuleSet::kalah(), state.pits(), state.rules()));
+            }
+            let legacy: HashMap<LegacyState, f64> = try!(datafile::load(path));
+            let mut table = Table::with_capacity(legacy.len());
+            for (legacy_state, value) in legacy {
+                let converted = try!(legacy_state.state()
+                                     .map_err(|e| format!("{} holds a bad state: {}", path, e)));
+                table.values.insert(converted, value);
+            }
+            try!(table.save(path, &header));
+        }
+        Model::Network => {
+            let network: Network = try!(datafile::load(path));
+            if network.pits() != state.pits() {
+                return Err(format!("{} holds a network for {} pits, not {}", path,
+                                   network.pits(), state.pits()));
+            }
+            try!(datafile::save_values(&network, &header, path));
+        }
+        Model::Linear => {
+            let linear: Linear = try!(datafile::load(path));
+            try!(datafile::save_values(&linear, &header, path));
+        }
+    }
+    if has_history {
+        try!(fs::remove_file(&history_path)
+             .map_err(|e| format!("can't remove {}: {}", history_path, e)));
+    }
+    Ok(header)
+}
+
+/// What states never learned are worth, and so where learning them starts from
+pub enum Initial {
+    Constant(f64),
+    /// From the store difference: 0.5 when level, up to 1 with every seed in our store
+    StoreDifference,
+    /// Whatever another value function, typically trained on an earlier run, says
+    Prior(Box<ValueFunction>),
+}
+
+impl Initial {
+    pub fn value(&self, state: &GameState) -> f64 {
+        match *self {
+            Initial::Constant(value) => value,
+            Initial::StoreDifference => {
+                let pits = state.pits();
+                let houses = state.houses();
+                let total = houses.iter().map(|&h| h as f64).sum::<f64>().max(1.0);
+                0.5 + 0.5 * (houses[pits] as f64 - houses[2 * pits + 1] as f64) / total
+            }
+            Initial::Prior(ref prior) => prior.value(state),
+        }
+    }
+}
+
+/// Gives the states a value function has never learned an `Initial` value, counting how often
+/// that happens
+pub struct Initialized<'a> {
+    values: &'a mut ValueFunction,
+    initial: &'a Initial,
+    lookups: Cell<usize>,
+    unseen: Cell<usize>,
+}
+
+impl<'a> Initialized<'a> {
+    pub fn new(values: &'a mut ValueFunction, initial: &'a Initial) -> Initialized<'a> {
+        Initialized { values: values, initial: initial, lookups: Cell::new(0),
+                      unseen: Cell::new(0) }
+    }
+
+    /// How many states have been looked up, and how many of those had never been learned
+    pub fn lookups(&self) -> (usize, usize) {
+        (self.lookups.get(), self.unseen.get())
+    }
+}
+
+impl<'a> ValueFunction for Initialized<'a> {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        self.lookups.set(self.lookups.get() + 1);
+        match self.values.get(state) {
+            Some(value) => Some(value),
+            None => {
+                self.unseen.set(self.unseen.get() + 1);
+                Some(self.initial.value(state))
+            }
+        }
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        if self.values.get(state).is_none() {
+            let initial = self.initial.value(state);
+            self.values.update(state, initial, 1.0);
+        }
+        self.values.update(state, target, learning_rate);
+    }
+
+    fn visit(&mut self, state: &GameState) {
+        self.values.visit(state)
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        self.values.save(path, header)
+    }
+}
+
+/// Write a table's `values` and `visits` to the training datafile at `path`, as a
+/// `CompactTable` if the header says so
+fn save_table(values: &HashMap<GameState, f64>, visits: &HashMap<GameState, u32>,
+              header: &Header, path: &str) -> Result<(), String> {
+    if header.model == Model::Compact {
+        CompactTable::save(values, visits, header, path)
+    } else {
+        datafile::save_values(&(values, visits), header, path)
+    }
+}
+
+/// The lookup table: one value for every state seen in training, none for anything else. It
+/// doesn't count visits, use a `Table` for that.
+impl ValueFunction for HashMap<GameState, f64> {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        HashMap::get(self, state).cloned()
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        let value = self.entry(*state).or_insert(DEFAULT_STATE_VAL);
+        *value += learning_rate * (target - *value);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    /// Saved as a `CompactTable` if the header says so
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        save_table(self, &HashMap::new(), header, path)
+    }
+}
+
+/// The lookup table as trained and saved: values, along with how many times training has
+/// played into each state, which says how much to trust them
+#[derive(Debug, PartialEq, Clone)]
+pub struct Table {
+    pub values: HashMap<GameState, f64>,
+    pub visits: HashMap<GameState, u32>,
+}
+
+impl Table {
+    pub fn with_capacity(capacity: usize) -> Table {
+        Table { values: HashMap::with_capacity(capacity), visits: HashMap::with_capacity(capacity) }
+    }
+
+    /// How many times training has played into `state`
+    pub fn visits(&self, state: &GameState) -> u32 {
+        self.visits.get(state).cloned().unwrap_or(0)
+    }
+}
+
+impl ValueFunction for Table {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        ValueFunction::get(&self.values, state)
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        self.values.update(state, target, learning_rate)
+    }
+
+    fn visit(&mut self, state: &GameState) {
+        let visits = self.visits.entry(*state).or_insert(0);
+        *visits = visits.saturating_add(1);
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Saved as a `CompactTable` if the header says so
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        save_table(&self.values, &self.visits, header, path)
+    }
+}
+
+/// A value function that ignores updates, for playing with values from disk without any chance
+/// of changing them
+pub struct ReadOnly {
+    values: Box<ValueFunction>,
+}
+
+impl ReadOnly {
+    pub fn new(values: Box<ValueFunction>) -> ReadOnly {
+        ReadOnly { values: values }
+    }
+}
+
+impl ValueFunction for ReadOnly {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        self.values.get(state)
+    }
+
+    fn update(&mut self, _: &GameState, _: f64, _: f64) {}
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn save(&self, path: &str, header: &Header) -> Result<(), String> {
+        self.values.save(path, header)
+    }
+}
+
+/// The average of several value functions, such as ones from separate training runs, over
+/// those that have an estimate for the state. Updates go to every member.
+pub struct Ensemble {
+    members: Vec<Box<ValueFunction>>,
+}
+
+impl Ensemble {
+    pub fn new(members: Vec<Box<ValueFunction>>) -> Ensemble {
+        Ensemble { members: members }
+    }
+}
+
+impl ValueFunction for Ensemble {
+    fn get(&self, state: &GameState) -> Option<f64> {
+        let estimates: Vec<f64> = self.members.iter().filter_map(|m| m.get(state)).collect();
+        if estimates.is_empty() {
+            None
+        } else {
+            Some(estimates.iter().sum::<f64>() / estimates.len() as f64)
+        }
+    }
+
+    fn update(&mut self, state: &GameState, target: f64, learning_rate: f64) {
+        for member in &mut self.members {
+            member.update(state, target, learning_rate);
+        }
+    }
+
+    fn visit(&mut self, state: &GameState) {
+        for member in &mut self.members {
+            member.visit(state);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.members.iter().map(|member| member.len()).sum()
+    }
+
+    fn save(&self, _: &str, _: &Header) -> Result<(), String> {
+        Err("an ensemble can't be saved as one datafile, save its members instead".to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::path::Path;
+    use ::datafile::{self, Header};
+    use ::learning::TrainingHistory;
+    use ::linear::Linear;
+    use ::mancala::{GameState, RuleSet};
+    use ::notation::Position;
+
+    #[test]
+    fn test_table() {
+        let (seen, unseen) = (GameState::new(4), GameState::new(3));
+        let mut table: HashMap<GameState, f64> = HashMap::new();
+        table.update(&seen, 1.0, 0.5);
+        assert_eq!(ValueFunction::get(&table, &seen), Some(0.75));
+        assert_eq!(ValueFunction::get(&table, &unseen), None);
+        assert_eq!(table.value(&unseen), DEFAULT_STATE_VAL);
+        // A full step lands right on the target
+        table.update(&seen, 0.0, 1.0);
+        assert_eq!(table.value(&seen), 0.0);
+
+        let path = env::temp_dir().join("mancala-test-table.dat");
+        let path = path.to_str().unwrap();
+        table.save(path, &Header::new(Model::Table, &seen, TrainingHistory::new())).unwrap();
+        let loaded = load(path, &seen).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.get(&seen), Some(0.0));
+        assert!(load("no-such-datafile.dat", &seen).is_err());
+        // Values only load for the board they were trained on
+        let other = GameState::with_board(4, 3, RuleSet::kalah());
+        assert!(load(path, &other).err().unwrap().contains("was trained for 6 pit"));
+    }
+
+    #[test]
+    fn test_table_visits() {
+        let (state, other) = (GameState::new(4), GameState::new(3));
+        let mut table = Table::with_capacity(2);
+        table.update(&state, 1.0, 0.5);
+        table.update(&other, 0.0, 0.5);
+        table.visit(&state);
+        table.visit(&state);
+        assert_eq!((table.visits(&state), table.visits(&other)), (2, 0));
+
+        let path = env::temp_dir().join("mancala-test-table-visits.dat");
+        let path = path.to_str().unwrap();
+        let mut header = Header::new(Model::Table, &state, TrainingHistory::new());
+        table.save(path, &header).unwrap();
+        assert_eq!(load_table(path, &state).unwrap(), table);
+        // Tables from before visits were counted still load, with none
+        header.version = 1;
+        datafile::save_values(&table.values, &header, path).unwrap();
+        let old = load_table(path, &state).unwrap();
+        assert_eq!((old.values, old.visits.len()), (table.values, 0));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let state = GameState::new(4);
+        let path = env::temp_dir().join("mancala-test-migrate.dat");
+        let path = path.to_str().unwrap();
+        let history_path = format!("{}.history", path);
+        // Legacy tables were keyed by the afterstate's 14 houses alone
+        let afterstate = state.afterstate(state.gen_actions().next().unwrap());
+        let mut houses = [0; 14];
+        houses.copy_from_slice(afterstate.houses());
+        let mut table: HashMap<LegacyState, f64> = HashMap::new();
+        table.insert(LegacyState { houses: houses }, 0.25);
+        datafile::save(&table, path).unwrap();
+        datafile::save(&TrainingHistory::new(), &history_path).unwrap();
+        assert!(load(path, &state).err().unwrap().contains("mancala migrate"));
+        // Legacy tables were all for 6 pit Kalah
+        assert!(migrate(path, Model::Table, &GameState::with_board(4, 3, RuleSet::kalah()))
+                .is_err());
+
+        let header = migrate(path, Model::Table, &state).unwrap();
+        assert_eq!((header.model, header.pits), (Model::Table, 6));
+        assert!(!Path::new(&history_path).exists());
+        assert_eq!(load(path, &state).unwrap().get(&afterstate), Some(0.25));
+        assert!(migrate(path, Model::Table, &state).is_err());
+    }
+
+    #[test]
+    fn test_read_only_ensemble() {
+        let (a, b) = (GameState::new(4), GameState::new(3));
+        let mut first: HashMap<GameState, f64> = HashMap::new();
+        first.insert(a, 1.0);
+        let mut second: HashMap<GameState, f64> = HashMap::new();
+        second.insert(a, 0.0);
+        second.insert(b, 0.8);
+        let mut ensemble = Ensemble::new(vec![Box::new(first), Box::new(second)]);
+        assert_eq!(ensemble.get(&a), Some(0.5));
+        // Members without an estimate don't drag the average towards the default
+        assert_eq!(ensemble.get(&b), Some(0.8));
+        assert_eq!(ensemble.get(&GameState::new(2)), None);
+        assert_eq!(ensemble.len(), 3);
+        ensemble.update(&b, 0.0, 1.0);
+        assert_eq!(ensemble.get(&b), Some(0.0));
+        let header = Header::new(Model::Table, &a, TrainingHistory::new());
+        assert!(ensemble.save("ensemble.dat", &header).is_err());
+
+        let mut read_only = ReadOnly::new(Box::new(Linear::new()));
+        read_only.update(&a, 1.0, 1.0);
+        assert_eq!(read_only.value(&a), 0.5);
+    }
+
+    #[test]
+    fn test_initialized() {
+        let start = GameState::with_board(4, 3, RuleSet::kalah());
+        let state = "0,4,4,4/2/3,3,3,3/0 2 kalah".parse::<Position>().unwrap().0;
+        let mut table: HashMap<GameState, f64> = HashMap::new();
+        {
+            let optimistic = Initial::Constant(OPTIMISTIC_STATE_VAL);
+            let mut values = Initialized::new(&mut table, &optimistic);
+            assert_eq!(values.value(&start), 1.0);
+            // Learning starts from the initial value rather than the default
+            values.update(&state, 0.0, 0.5);
+            assert_eq!(values.value(&state), 0.5);
+            assert_eq!(values.lookups(), (2, 1));
+        }
+        assert_eq!(ValueFunction::get(&table, &state), Some(0.5));
+
+        assert_eq!(Initial::StoreDifference.value(&start), 0.5);
+        assert_eq!(Initial::StoreDifference.value(&state), 0.5 + 0.5 * 2.0 / 26.0);
+        let prior = Initial::Prior(Box::new(table));
+        assert_eq!(prior.value(&state), 0.5);
+        assert_eq!(prior.value(&start), DEFAULT_STATE_VAL);
+    }
+}